@@ -0,0 +1,245 @@
+//! Prompts read from a JSONL file, answered one after the other.
+//!
+//! Every line of the input is an object with a `prompt` and, optionally, a
+//! `conversation` key and an `id` echoed back in the result:
+//!
+//! ```text
+//! {"prompt": "Name a prime number", "conversation": "primes"}
+//! {"prompt": "And the next one?", "conversation": "primes"}
+//! {"prompt": "Translate 'cat' to French", "id": 42}
+//! ```
+//!
+//! Lines sharing a `conversation` continue the same thread, the others start
+//! a new one. The output file doubles as the checkpoint: lines it already has
+//! a record of are skipped, so a crashed run picks up where it stopped. Lines
+//! Google refused for a while (see [`BatchError::is_retryable`]) are sent again.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::GeminiClient;
+use crate::error::{GeminiError, Result};
+use crate::response::{ConversationIds, GeminiResponse};
+
+/// One line of the input file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchPrompt {
+    pub prompt: String,
+    /// Lines with the same key continue the same conversation
+    #[serde(default)]
+    pub conversation: Option<String>,
+    /// Anything identifying the line, copied to its record
+    #[serde(default)]
+    pub id: Option<serde_json::Value>,
+}
+
+/// Outcome of one input line, written as a line of the output file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchRecord {
+    /// 1-based line number in the input file
+    pub line: usize,
+    pub id: Option<serde_json::Value>,
+    pub conversation: Option<String>,
+    pub prompt: String,
+    pub response: Option<GeminiResponse>,
+    pub error: Option<BatchError>,
+}
+
+/// Why a line has no response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchError {
+    /// [`GeminiError::kind`], or `invalid_line` when the line isn't a [`BatchPrompt`]
+    pub kind: String,
+    pub message: String,
+}
+
+impl BatchError {
+    /// `true` when Google refused the prompt without answering it (CAPTCHA,
+    /// rate limit, 5xx), so a later run sends it again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "captcha" | "rate_limited" | "server_error"
+        )
+    }
+}
+
+impl From<&GeminiError> for BatchError {
+    fn from(err: &GeminiError) -> Self {
+        Self {
+            kind: err.kind().to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Counts of a finished [`run`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub answered: usize,
+    pub failed: usize,
+    /// Lines the output file already had a record of
+    pub skipped: usize,
+}
+
+/// Answers every line of `input` not recorded in `output` yet, appending a
+/// [`BatchRecord`] to `output` for each, `delay` apart.
+///
+/// Failed lines are recorded and the run goes on, except when the cookies
+/// are rejected or Google blocks the session with a CAPTCHA or a rate limit
+/// the retries didn't get past: nothing after that could succeed for now, so
+/// the error is returned and the line is left for the next run.
+pub async fn run(
+    client: &mut GeminiClient,
+    input: &Path,
+    output: &Path,
+    delay: Duration,
+    mut on_record: impl FnMut(&BatchRecord),
+) -> Result<BatchSummary> {
+    let input = std::fs::read_to_string(input)?;
+    let lines: Vec<(usize, &str)> = input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+
+    let (done, mut conversations) = read_checkpoint(output)?;
+    let mut out = open_for_append(output)?;
+
+    let mut summary = BatchSummary::default();
+    let mut sent_any = false;
+    for (line, text) in lines.iter().copied() {
+        if done.contains(&line) {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let prompt = match serde_json::from_str::<BatchPrompt>(text) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                let record = BatchRecord {
+                    line,
+                    id: None,
+                    conversation: None,
+                    prompt: String::new(),
+                    response: None,
+                    error: Some(BatchError {
+                        kind: "invalid_line".to_string(),
+                        message: e.to_string(),
+                    }),
+                };
+                write_record(&mut out, &record)?;
+                summary.failed += 1;
+                on_record(&record);
+                continue;
+            }
+        };
+
+        if sent_any {
+            tokio::time::sleep(delay).await;
+        }
+        sent_any = true;
+
+        match &prompt.conversation {
+            Some(key) => {
+                client.set_conversation_ids(conversations.get(key).cloned().unwrap_or_default())
+            }
+            None => client.reset(),
+        }
+
+        let result = client.ask(&prompt.prompt).await;
+        if let Err(
+            err @ (GeminiError::InvalidCookies(_)
+            | GeminiError::Captcha
+            | GeminiError::RateLimited { .. }),
+        ) = result
+        {
+            return Err(err);
+        }
+        if let (Ok(_), Some(key)) = (&result, &prompt.conversation) {
+            conversations.insert(key.clone(), client.conversation_ids().clone());
+        }
+
+        let (response, error) = match result {
+            Ok(response) => {
+                summary.answered += 1;
+                (Some(response), None)
+            }
+            Err(e) => {
+                summary.failed += 1;
+                (None, Some(BatchError::from(&e)))
+            }
+        };
+        let record = BatchRecord {
+            line,
+            id: prompt.id,
+            conversation: prompt.conversation,
+            prompt: prompt.prompt,
+            response,
+            error,
+        };
+        write_record(&mut out, &record)?;
+        on_record(&record);
+    }
+
+    Ok(summary)
+}
+
+/// Lines `output` has an answer or a lasting failure for, and where each
+/// conversation stopped.
+fn read_checkpoint(output: &Path) -> Result<(BTreeSet<usize>, HashMap<String, ConversationIds>)> {
+    let mut done = BTreeSet::new();
+    let mut conversations = HashMap::new();
+
+    let text = match std::fs::read_to_string(output) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((done, conversations)),
+        Err(e) => return Err(e.into()),
+    };
+    // a line cut short by a crash doesn't parse, so it is sent again
+    for record in text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<BatchRecord>(line).ok())
+        .filter(|record| !record.error.as_ref().is_some_and(BatchError::is_retryable))
+    {
+        done.insert(record.line);
+        if let (Some(key), Some(response)) = (record.conversation, record.response) {
+            conversations.insert(key, response.conversation_ids);
+        }
+    }
+    Ok((done, conversations))
+}
+
+fn open_for_append(output: &Path) -> Result<std::fs::File> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .read(true)
+        .open(output)?;
+
+    // start on a new line after a record cut short by a crash
+    let len = file.metadata()?.len();
+    if len > 0 {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut last = [0u8];
+        file.seek(SeekFrom::Start(len - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+        }
+    }
+    Ok(file)
+}
+
+fn write_record(out: &mut std::fs::File, record: &BatchRecord) -> Result<()> {
+    let json =
+        serde_json::to_string(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    writeln!(out, "{json}")?;
+    out.flush()?;
+    Ok(())
+}
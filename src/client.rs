@@ -0,0 +1,686 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::Stream;
+use rand::Rng;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE, RETRY_AFTER, USER_AGENT};
+use serde_json::json;
+use url::Url;
+
+use crate::cookies::{
+    CookieRotator, Cookies, RotateCallback, RotationTask, DEFAULT_ROTATE_COOKIES_URL,
+    DEFAULT_ROTATE_INTERVAL, PSID, PSIDTS,
+};
+use crate::error::{GeminiError, Result};
+use crate::parser;
+use crate::ratelimit::RateLimiter;
+use crate::response::{ConversationIds, GeminiResponse, Image, ImageKind};
+use crate::retry::RetryPolicy;
+use crate::stream::{self, StreamEvent};
+use crate::upload::{self, Attachment, DEFAULT_UPLOAD_URL};
+
+/// Gemini web app every request is sent to by default.
+pub const DEFAULT_BASE_URL: &str = "https://gemini.google.com/";
+/// RPC answering prompts, relative to the base URL.
+pub const DEFAULT_STREAM_GENERATE_PATH: &str =
+    "_/BardChatUi/data/assistant.lamda.BardFrontendService/StreamGenerate";
+
+/// `bl` sent when the homepage doesn't expose the current build label.
+pub const DEFAULT_BUILD_LABEL: &str = "boq_assistant-bard-web-server_20240717.08_p5";
+
+/// Host serving generated images, the only one the session cookies go to.
+const GENERATED_IMAGE_HOST: &str = "lh3.googleusercontent.com";
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36";
+
+/// Builder for [`GeminiClient`].
+#[derive(Clone)]
+pub struct GeminiClientBuilder {
+    cookies: Cookies,
+    proxy: Option<String>,
+    base_url: Option<String>,
+    stream_generate_path: Option<String>,
+    rotate_cookies_url: Option<String>,
+    rotate_interval: Option<Duration>,
+    on_cookies_rotated: Option<RotateCallback>,
+    build_label: Option<String>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    language: Option<String>,
+    upload_url: Option<String>,
+}
+
+impl Default for GeminiClientBuilder {
+    fn default() -> Self {
+        Self {
+            cookies: Cookies::default(),
+            proxy: None,
+            base_url: None,
+            stream_generate_path: None,
+            rotate_cookies_url: None,
+            rotate_interval: Some(DEFAULT_ROTATE_INTERVAL),
+            on_cookies_rotated: None,
+            build_label: None,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            language: None,
+            upload_url: None,
+        }
+    }
+}
+
+impl fmt::Debug for GeminiClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeminiClientBuilder")
+            .field(
+                "cookies",
+                &self
+                    .cookies
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("proxy", &self.proxy)
+            .field("base_url", &self.base_url)
+            .field("stream_generate_path", &self.stream_generate_path)
+            .field("rotate_cookies_url", &self.rotate_cookies_url)
+            .field("rotate_interval", &self.rotate_interval)
+            .field("build_label", &self.build_label)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("language", &self.language)
+            .field("upload_url", &self.upload_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GeminiClientBuilder {
+    /// `__Secure-1PSID` cookie, usually starts with `g.`
+    pub fn psid(mut self, psid: impl Into<String>) -> Self {
+        self.cookies.set(PSID, psid);
+        self
+    }
+
+    /// `__Secure-1PSIDTS` cookie
+    pub fn psidts(mut self, psidts: impl Into<String>) -> Self {
+        self.cookies.set(PSIDTS, psidts);
+        self
+    }
+
+    /// Extra cookies, e.g. every Google cookie of a browser export
+    /// (see [`Cookies::from_file`]). Cookies already set, like the ones of
+    /// [`psid`](Self::psid) and [`psidts`](Self::psidts), are kept.
+    pub fn cookies(mut self, cookies: Cookies) -> Self {
+        for (name, value) in cookies.iter() {
+            if self.cookies.get(name).is_none() {
+                self.cookies.set(name, value);
+            }
+        }
+        self
+    }
+
+    /// Proxy server every request goes through. Empty string means no proxy.
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        let proxy = proxy.into();
+        self.proxy = (!proxy.is_empty()).then_some(proxy);
+        self
+    }
+
+    /// Origin of the web app, e.g. a local mock server or a corporate reverse proxy.
+    /// Empty string means [`DEFAULT_BASE_URL`].
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into();
+        self.base_url = (!base_url.is_empty()).then_some(base_url);
+        self
+    }
+
+    /// Path of the `StreamGenerate` RPC, relative to the base URL.
+    /// Empty string means [`DEFAULT_STREAM_GENERATE_PATH`].
+    pub fn stream_generate_path(mut self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        self.stream_generate_path = (!path.is_empty()).then_some(path);
+        self
+    }
+
+    /// Endpoint refreshing `__Secure-1PSIDTS`.
+    /// Empty string means [`DEFAULT_ROTATE_COOKIES_URL`].
+    pub fn rotate_cookies_url(mut self, url: impl Into<String>) -> Self {
+        let url = url.into();
+        self.rotate_cookies_url = (!url.is_empty()).then_some(url);
+        self
+    }
+
+    /// How often the cookies are rotated in the background, `None` to only
+    /// rotate them when Google rejects the session. Defaults to [`DEFAULT_ROTATE_INTERVAL`].
+    pub fn rotate_interval(mut self, interval: Option<Duration>) -> Self {
+        self.rotate_interval = interval;
+        self
+    }
+
+    /// Called with the new `__Secure-1PSIDTS` after each rotation, e.g. to save it.
+    pub fn on_cookies_rotated(mut self, callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_cookies_rotated = Some(Arc::new(callback));
+        self
+    }
+
+    /// Server build label (`bl`) sent when it can't be scraped from the homepage.
+    /// Empty string means [`DEFAULT_BUILD_LABEL`].
+    pub fn build_label(mut self, build_label: impl Into<String>) -> Self {
+        let build_label = build_label.into();
+        self.build_label = (!build_label.is_empty()).then_some(build_label);
+        self
+    }
+
+    /// When `ask` sends a rejected prompt again. Defaults to [`RetryPolicy::default`].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Throttles the requests of this client.
+    /// Defaults to [`RateLimiter::shared`], shared by every client of the process.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Language Gemini answers in (`hl`), e.g. `en` or `ko`.
+    /// Empty string means the account's language.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        let language = language.into();
+        self.language = (!language.is_empty()).then_some(language);
+        self
+    }
+
+    /// Endpoint images are uploaded to before being sent with a prompt.
+    /// Empty string means [`DEFAULT_UPLOAD_URL`].
+    pub fn upload_url(mut self, url: impl Into<String>) -> Self {
+        let url = url.into();
+        self.upload_url = (!url.is_empty()).then_some(url);
+        self
+    }
+
+    /// Opens the Gemini homepage with the cookies and extracts the `SNlM0e` token,
+    /// the build label and the session id.
+    pub async fn build(self) -> Result<GeminiClient> {
+        let endpoints = Endpoints::new(
+            self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL),
+            self.stream_generate_path
+                .as_deref()
+                .unwrap_or(DEFAULT_STREAM_GENERATE_PATH),
+        )?;
+        let rotate_cookies_url = self
+            .rotate_cookies_url
+            .as_deref()
+            .unwrap_or(DEFAULT_ROTATE_COOKIES_URL);
+        let rotate_cookies_url = Url::parse(rotate_cookies_url).map_err(|e| {
+            GeminiError::InvalidConfig(format!(
+                "invalid rotate cookies URL {rotate_cookies_url:?}: {e}"
+            ))
+        })?;
+
+        let upload_url = self.upload_url.as_deref().unwrap_or(DEFAULT_UPLOAD_URL);
+        let upload_url = Url::parse(upload_url).map_err(|e| {
+            GeminiError::InvalidConfig(format!("invalid upload URL {upload_url:?}: {e}"))
+        })?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+
+        let client_builder = match &self.proxy {
+            Some(proxy_server) => reqwest::Client::builder()
+                .default_headers(headers)
+                .proxy(reqwest::Proxy::all(proxy_server)?),
+            None => reqwest::Client::builder().default_headers(headers),
+        };
+
+        let client = client_builder.build()?;
+        let rotator = Arc::new(CookieRotator::new(
+            client.clone(),
+            rotate_cookies_url,
+            self.cookies,
+            self.on_cookies_rotated,
+        ));
+
+        let rate_limiter = self
+            .rate_limiter
+            .unwrap_or_else(|| RateLimiter::shared().clone());
+
+        // An expired __Secure-1PSIDTS can be refreshed without logging in again
+        rate_limiter.acquire().await;
+        let tokens = match fetch_page_tokens(&client, &endpoints, &rotator).await {
+            Err(e @ (GeminiError::InvalidCookies(_) | GeminiError::TokenNotFound)) => {
+                if !matches!(rotator.rotate().await, Ok(true)) {
+                    return Err(e);
+                }
+                rate_limiter.acquire().await;
+                fetch_page_tokens(&client, &endpoints, &rotator).await?
+            }
+            result => result?,
+        };
+
+        let reqid: u64 = rand::thread_rng().gen_range(100000..999999);
+        let rotation = self
+            .rotate_interval
+            .map(|interval| rotator.spawn_timer(interval));
+
+        Ok(GeminiClient {
+            client,
+            endpoints,
+            rotator,
+            _rotation: rotation,
+            reqid,
+            snlm0e: tokens.snlm0e,
+            build_label: tokens.build_label.unwrap_or_else(|| {
+                self.build_label
+                    .unwrap_or_else(|| DEFAULT_BUILD_LABEL.to_string())
+            }),
+            session_id: tokens.session_id,
+            retry_policy: self.retry_policy,
+            rate_limiter,
+            language: self.language,
+            upload_url,
+            conversation: ConversationIds::default(),
+            attachments: Vec::new(),
+        })
+    }
+}
+
+/// A single Gemini conversation backed by the web app session.
+pub struct GeminiClient {
+    client: reqwest::Client,
+    endpoints: Endpoints,
+    rotator: Arc<CookieRotator>,
+    _rotation: Option<RotationTask>,
+    reqid: u64,
+    snlm0e: String,
+    /// `bl`, the server build label (`cfb2h`)
+    build_label: String,
+    /// `f.sid` (`FdrFJe`)
+    session_id: Option<String>,
+    retry_policy: RetryPolicy,
+    rate_limiter: RateLimiter,
+    language: Option<String>,
+    upload_url: Url,
+    conversation: ConversationIds,
+    /// Uploaded files the next prompt is sent with
+    attachments: Vec<Attachment>,
+}
+
+impl GeminiClient {
+    pub fn builder() -> GeminiClientBuilder {
+        GeminiClientBuilder::default()
+    }
+
+    /// Shorthand for `GeminiClient::builder().psid(..).psidts(..).build()`.
+    pub async fn new(_1psid: &str, _1psidts: &str) -> Result<Self> {
+        Self::builder().psid(_1psid).psidts(_1psidts).build().await
+    }
+
+    /// Sends `message` in the current conversation and remembers the returned ids
+    /// so the next call continues the same thread.
+    pub async fn ask(&mut self, message: &str) -> Result<GeminiResponse> {
+        let text = self.send_stream_generate(message).await?.text().await?;
+        let results = parser::parse_stream_generate(&text).map_err(|e| {
+            if text.contains("CAPTCHA") {
+                GeminiError::Captcha
+            } else {
+                e
+            }
+        })?;
+
+        self.finish_turn(&results);
+
+        Ok(results)
+    }
+
+    /// Like [`ask`](Self::ask), but yields the answer as it is generated.
+    ///
+    /// The stream produces [`StreamEvent::Delta`]s of the default answer and ends with
+    /// [`StreamEvent::Done`] holding the full response. The conversation only moves
+    /// forward once the stream has been driven to `Done`.
+    pub async fn ask_stream(
+        &mut self,
+        message: &str,
+    ) -> Result<impl Stream<Item = Result<StreamEvent>> + '_> {
+        let resp = self.send_stream_generate(message).await?;
+        Ok(stream::answer_stream(self, resp))
+    }
+
+    /// Server build label sent as `bl`, scraped from the homepage when possible.
+    pub fn build_label(&self) -> &str {
+        &self.build_label
+    }
+
+    /// Current cookies, including a rotated `__Secure-1PSIDTS`.
+    pub fn cookies(&self) -> Cookies {
+        self.rotator.cookies()
+    }
+
+    /// Refreshes `__Secure-1PSIDTS` now instead of waiting for the timer.
+    /// Returns `false` when Google didn't hand out a new value.
+    pub async fn rotate_cookies(&self) -> Result<bool> {
+        self.rotator.rotate().await
+    }
+
+    /// Uploads an image (or any file Gemini can read) and attaches it to the next prompt.
+    pub async fn attach_image(&mut self, path: impl AsRef<Path>) -> Result<&Attachment> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| {
+                GeminiError::InvalidConfig(format!("{} is not a file", path.display()))
+            })?;
+        let bytes = tokio::fs::read(path).await?;
+
+        let attachment = upload::upload(&self.client, &self.upload_url, &name, bytes).await?;
+        self.attachments.push(attachment);
+        Ok(self.attachments.last().unwrap())
+    }
+
+    /// Files the next prompt will be sent with.
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    /// Drops the attachments instead of sending them with the next prompt.
+    pub fn clear_attachments(&mut self) {
+        self.attachments.clear();
+    }
+
+    /// Downloads `image` into `dir` as `name` plus the extension of its
+    /// content type, and returns the path written.
+    ///
+    /// Generated images are only served with the session cookies, which are
+    /// never sent anywhere else: web images can be hosted by anyone.
+    pub async fn save_image(
+        &self,
+        image: &Image,
+        dir: impl AsRef<Path>,
+        name: &str,
+    ) -> Result<PathBuf> {
+        let url = Url::parse(&image.url)
+            .map_err(|e| GeminiError::InvalidConfig(format!("invalid image URL: {e}")))?;
+        let request = self.client.get(url.clone());
+        let resp =
+            if image.kind == ImageKind::Generated && url.host_str() == Some(GENERATED_IMAGE_HOST) {
+                let request = request.header(COOKIE, self.rotator.header()?);
+                check_status(request.send().await?)?
+            } else {
+                // a 401 from someone else's server says nothing about the session
+                request.send().await?.error_for_status()?
+            };
+        let extension = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(image_extension)
+            .unwrap_or("png");
+        let bytes = resp.bytes().await?;
+
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(format!("{name}.{extension}"));
+        tokio::fs::write(&path, &bytes).await?;
+        Ok(path)
+    }
+
+    /// Remembers the ids of `response` so the next `ask` continues from it.
+    /// The attachments went with the prompt, so they are dropped.
+    pub(crate) fn finish_turn(&mut self, response: &GeminiResponse) {
+        self.conversation = response.conversation_ids.clone();
+        self.attachments.clear();
+        self.reqid += 100000;
+    }
+
+    /// Sends the prompt, retrying as the retry policy allows.
+    async fn send_stream_generate(&self, message: &str) -> Result<reqwest::Response> {
+        let mut retry = 0;
+        loop {
+            match self.send_stream_generate_once(message).await {
+                Err(e) => match self.retry_policy.delay_for(retry, &e) {
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        retry += 1;
+                    }
+                    None => return Err(e),
+                },
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_stream_generate_once(&self, message: &str) -> Result<reqwest::Response> {
+        // attachments go after the text as `[[file_id, 1], name]`
+        let prompt = if self.attachments.is_empty() {
+            json!([message])
+        } else {
+            let files: Vec<_> = self
+                .attachments
+                .iter()
+                .map(|file| json!([[file.file_id, 1], file.name]))
+                .collect();
+            json!([message, 0, (), files])
+        };
+        let message_struct = json!([
+            prompt,
+            (),
+            [
+                self.conversation.conversation_id,
+                self.conversation.response_id,
+                self.conversation.choice_id
+            ],
+        ]);
+        let form_data = json!([(), message_struct.to_string()]).to_string();
+
+        let body_data = format!(
+            "f.req={}&at={}&",
+            urlencoding::encode(&form_data),
+            urlencoding::encode(&self.snlm0e)
+        );
+
+        let mut encoded = self.endpoints.stream_generate.clone();
+        encoded
+            .query_pairs_mut()
+            .append_pair("bl", &self.build_label);
+        if let Some(session_id) = &self.session_id {
+            encoded.query_pairs_mut().append_pair("f.sid", session_id);
+        }
+        if let Some(language) = &self.language {
+            encoded.query_pairs_mut().append_pair("hl", language);
+        }
+        encoded
+            .query_pairs_mut()
+            .append_pair("_reqid", &self.reqid.to_string())
+            .append_pair("rt", "c");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Content-Type",
+            HeaderValue::from_static("application/x-www-form-urlencoded;charset=UTF-8"),
+        );
+        headers.insert("Origin", self.endpoints.origin.clone());
+        headers.insert("Referer", self.endpoints.referer.clone());
+
+        let send = || async {
+            self.rate_limiter.acquire().await;
+            let post_resp = self
+                .client
+                .post(encoded.clone())
+                .headers(headers.clone())
+                .header(COOKIE, self.rotator.header()?)
+                .body(body_data.clone())
+                .send()
+                .await?;
+            check_status(post_resp)
+        };
+
+        match send().await {
+            // Retry once with a fresh __Secure-1PSIDTS
+            Err(e @ GeminiError::InvalidCookies(_)) => {
+                if !matches!(self.rotator.rotate().await, Ok(true)) {
+                    return Err(e);
+                }
+                send().await
+            }
+            result => result,
+        }
+    }
+
+    /// Ids the next `ask` continues from.
+    pub fn conversation_ids(&self) -> &ConversationIds {
+        &self.conversation
+    }
+
+    /// Continues the conversation `ids` points to, e.g. one restored from a [`Session`](crate::session::Session).
+    pub fn set_conversation_ids(&mut self, ids: ConversationIds) {
+        self.conversation = ids;
+    }
+
+    /// Forgets the conversation ids so the next `ask` starts a new thread.
+    pub fn reset(&mut self) {
+        self.conversation = ConversationIds::default();
+    }
+}
+
+/// Values the web app embeds in the homepage and sends back with every request.
+struct PageTokens {
+    snlm0e: String,
+    build_label: Option<String>,
+    session_id: Option<String>,
+}
+
+async fn fetch_page_tokens(
+    client: &reqwest::Client,
+    endpoints: &Endpoints,
+    rotator: &CookieRotator,
+) -> Result<PageTokens> {
+    // 1. GET request to the homepage (https://gemini.google.com/)
+    let resp = client
+        .get(endpoints.base.clone())
+        .header(COOKIE, rotator.header()?)
+        .send()
+        .await?;
+    let resp = check_status(resp)?;
+
+    // Expired cookies end up on the sign-in page
+    if resp
+        .url()
+        .host_str()
+        .is_some_and(|host| host.starts_with("accounts."))
+    {
+        return Err(GeminiError::InvalidCookies(
+            "redirected to the Google sign-in page".to_string(),
+        ));
+    }
+    let body = resp.text().await?;
+
+    // 2. Check if the body contains the word "CAPTCHA"
+    if body.contains("CAPTCHA") {
+        return Err(GeminiError::Captcha);
+    }
+
+    // 3. Extract SNlM0e, cfb2h (bl) and FdrFJe (f.sid) values using regex
+    let extract = |key: &str| {
+        Regex::new(&format!(r#""{key}":"(.*?)""#))
+            .unwrap()
+            .captures(&body)
+            .and_then(|caps| caps.get(1).map(|m| m.as_str().to_owned()))
+            .filter(|value| !value.is_empty())
+    };
+
+    Ok(PageTokens {
+        snlm0e: extract("SNlM0e").ok_or(GeminiError::TokenNotFound)?,
+        build_label: extract("cfb2h"),
+        session_id: extract("FdrFJe"),
+    })
+}
+
+/// URLs and headers derived from the configured base URL.
+#[derive(Debug, Clone)]
+struct Endpoints {
+    base: Url,
+    stream_generate: Url,
+    origin: HeaderValue,
+    referer: HeaderValue,
+}
+
+impl Endpoints {
+    fn new(base_url: &str, stream_generate_path: &str) -> Result<Self> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            GeminiError::InvalidConfig(format!("invalid base URL {base_url:?}: {e}"))
+        };
+
+        // Without the trailing slash, `join` would drop the last path segment
+        let mut base = Url::parse(base_url).map_err(|e| invalid(&e))?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        let stream_generate = base
+            .join(stream_generate_path.trim_start_matches('/'))
+            .map_err(|e| invalid(&e))?;
+        let origin =
+            HeaderValue::from_str(&base.origin().ascii_serialization()).map_err(|e| invalid(&e))?;
+        let referer = HeaderValue::from_str(base.as_str()).map_err(|e| invalid(&e))?;
+
+        Ok(Self {
+            base,
+            stream_generate,
+            origin,
+            referer,
+        })
+    }
+}
+
+/// File extension for an image `Content-Type`.
+fn image_extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim();
+    Some(match mime {
+        "image/png" => "png",
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "image/avif" => "avif",
+        _ => return None,
+    })
+}
+
+/// Maps the status codes Google uses for blocks and bad sessions to [`GeminiError`].
+pub(crate) fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    // Blocked clients are sent to https://www.google.com/sorry/index
+    if resp.url().path().starts_with("/sorry/") {
+        return Err(GeminiError::Captcha);
+    }
+
+    let status = resp.status();
+    let retry_after = retry_after(&resp);
+    match status {
+        reqwest::StatusCode::TOO_MANY_REQUESTS => Err(GeminiError::RateLimited { retry_after }),
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Err(
+            GeminiError::InvalidCookies(format!("server answered {status}")),
+        ),
+        status if status.is_server_error() => Err(GeminiError::ServerError {
+            status: status.as_u16(),
+            retry_after,
+        }),
+        _ => Ok(resp.error_for_status()?),
+    }
+}
+
+/// `Retry-After`, either in seconds or as an HTTP date.
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
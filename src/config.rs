@@ -0,0 +1,97 @@
+//! `config.toml` with named profiles.
+//!
+//! ```toml
+//! default_profile = "work"
+//!
+//! [profiles.work]
+//! cookies_file = "/home/me/cookies.txt"
+//! proxy = "http://proxy.corp:3128"
+//! history_dir = "/home/me/notes/gemini"
+//! language = "en"
+//!
+//! [profiles.personal]
+//! psid = "g.a000..."
+//! psidts = "sidts-..."
+//! spinner = "◐◐◓◓◑◑◒◒"
+//! multi = true
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{GeminiError, Result};
+
+/// Settings of one profile. Everything is optional, missing values fall back to defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// `__Secure-1PSID`
+    pub psid: Option<String>,
+    /// `__Secure-1PSIDTS`
+    pub psidts: Option<String>,
+    /// Netscape `cookies.txt` or Firefox `cookies.sqlite`
+    pub cookies_file: Option<PathBuf>,
+    pub proxy: Option<String>,
+    pub base_url: Option<String>,
+    /// Directory the markdown transcripts are saved to
+    pub history_dir: Option<PathBuf>,
+    /// Tick characters of the progress spinner
+    pub spinner: Option<String>,
+    /// Show every draft of an answer
+    pub multi: Option<bool>,
+    /// Format answers as markdown in the terminal
+    pub render: Option<bool>,
+    /// Language Gemini answers in (`hl`), e.g. `en` or `ko`
+    pub language: Option<String>,
+    pub retries: Option<u32>,
+    pub rate_limit_rpm: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+    pub rate_limit_min_gap_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Profile used when none is asked for, `default` if unset
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// `config.toml` in the `bard-rs` config directory, e.g. `~/.config/bard-rs/config.toml` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("bard-rs").join("config.toml"))
+    }
+
+    pub fn parse(toml: &str) -> Result<Self> {
+        toml::from_str(toml).map_err(|e| GeminiError::InvalidConfig(e.to_string()))
+    }
+
+    /// Reads `path`. A missing file is an empty config.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(toml) => Self::parse(&toml)
+                .map_err(|e| GeminiError::InvalidConfig(format!("{}: {e}", path.display()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Profile `name`, or the default profile when `None`.
+    ///
+    /// Asking for a profile that doesn't exist is an error, a missing default profile is not.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name {
+            Some(name) => self.profiles.get(name).cloned().ok_or_else(|| {
+                GeminiError::InvalidConfig(format!("no profile named {name:?} in the config"))
+            }),
+            None => {
+                let name = self.default_profile.as_deref().unwrap_or("default");
+                Ok(self.profiles.get(name).cloned().unwrap_or_default())
+            }
+        }
+    }
+}
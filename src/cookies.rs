@@ -0,0 +1,288 @@
+//! Session cookies and the `__Secure-1PSIDTS` rotation flow.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE, COOKIE, SET_COOKIE};
+#[cfg(feature = "sqlite")]
+use rusqlite::OpenFlags;
+use url::Url;
+
+use crate::error::{GeminiError, Result};
+
+pub const PSID: &str = "__Secure-1PSID";
+pub const PSIDTS: &str = "__Secure-1PSIDTS";
+
+/// Endpoint the web app calls to refresh `__Secure-1PSIDTS`.
+pub const DEFAULT_ROTATE_COOKIES_URL: &str = "https://accounts.google.com/RotateCookies";
+/// How often the cookies are rotated in the background by default.
+pub const DEFAULT_ROTATE_INTERVAL: Duration = Duration::from_secs(9 * 60);
+
+/// Google answers `429` when the cookies are rotated more often than this.
+const MIN_ROTATE_GAP: Duration = Duration::from_secs(60);
+
+/// Called with the new `__Secure-1PSIDTS` every time it is rotated.
+pub type RotateCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Cookies sent with every request, by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Cookies(BTreeMap<String, String>);
+
+impl Cookies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Adds or replaces a cookie. Empty values are skipped.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let value = value.into();
+        if !value.is_empty() {
+            self.0.insert(name.into(), value);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Adds every cookie of `other`, replacing the ones with the same name.
+    pub fn extend(&mut self, other: Cookies) {
+        self.0.extend(other.0);
+    }
+
+    /// Google cookies of a browser export, either a Netscape `cookies.txt`
+    /// or an (unencrypted) Firefox `cookies.sqlite` with the `sqlite` feature.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(SQLITE_MAGIC) {
+            #[cfg(feature = "sqlite")]
+            return Self::from_firefox_sqlite(path);
+            #[cfg(not(feature = "sqlite"))]
+            return Err(GeminiError::InvalidCookies(format!(
+                "{} is a cookies.sqlite, which needs the sqlite feature",
+                path.display()
+            )));
+        }
+        let text = String::from_utf8(bytes).map_err(|_| {
+            GeminiError::InvalidCookies(format!("{} is not a cookies.txt file", path.display()))
+        })?;
+        Ok(Self::from_netscape(&text))
+    }
+
+    /// Google cookies of a Netscape `cookies.txt` export. Expired cookies are skipped.
+    pub fn from_netscape(text: &str) -> Self {
+        let now = unix_now();
+        text.lines()
+            .filter_map(|line| {
+                // curl marks HttpOnly cookies with a prefix instead of a column
+                let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+                if line.starts_with('#') {
+                    return None;
+                }
+                let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+                let [domain, _, _, _, expires, name, value] = fields[..] else {
+                    return None;
+                };
+                let expires: i64 = expires.parse().unwrap_or(0);
+                let expired = expires != 0 && expires < now;
+                (is_google_domain(domain) && !expired).then_some((name, value))
+            })
+            .collect()
+    }
+
+    /// Google cookies of a Firefox profile's `cookies.sqlite`.
+    /// The database is opened read-only, so it can be read while Firefox is running.
+    #[cfg(feature = "sqlite")]
+    pub fn from_firefox_sqlite(path: impl AsRef<Path>) -> Result<Self> {
+        let invalid = |e: rusqlite::Error| GeminiError::InvalidCookies(e.to_string());
+
+        let uri = Url::from_file_path(std::path::absolute(path.as_ref())?)
+            .map(|url| format!("{url}?immutable=1"))
+            .map_err(|_| {
+                GeminiError::InvalidCookies(format!("invalid path {}", path.as_ref().display()))
+            })?;
+        let conn = rusqlite::Connection::open_with_flags(
+            uri,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+        )
+        .map_err(invalid)?;
+
+        let mut stmt = conn
+            .prepare("SELECT host, name, value, expiry FROM moz_cookies ORDER BY lastAccessed")
+            .map_err(invalid)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })
+            .map_err(invalid)?;
+
+        // Firefox stores the expiry in seconds, some versions in milliseconds
+        let now = unix_now();
+        let mut cookies = Self::new();
+        for row in rows {
+            let (host, name, value, expiry) = row.map_err(invalid)?;
+            let expiry = if expiry > 100_000_000_000 {
+                expiry / 1000
+            } else {
+                expiry
+            };
+            if is_google_domain(&host) && (expiry == 0 || expiry >= now) {
+                cookies.set(name, value);
+            }
+        }
+        Ok(cookies)
+    }
+
+    /// Value of the `Cookie` header.
+    pub fn header(&self) -> Result<HeaderValue> {
+        let cookie = self
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&cookie).map_err(|e| GeminiError::InvalidCookies(e.to_string()))
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Cookies {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut cookies = Self::new();
+        for (name, value) in iter {
+            cookies.set(name, value);
+        }
+        cookies
+    }
+}
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// `google.com` and its subdomains, the cookies Gemini and the accounts endpoints read.
+fn is_google_domain(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    domain == "google.com" || domain.ends_with(".google.com")
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Shared between the client and its background rotation task.
+pub(crate) struct CookieRotator {
+    http: reqwest::Client,
+    url: Url,
+    cookies: RwLock<Cookies>,
+    last_rotation: Mutex<Option<Instant>>,
+    on_rotated: Option<RotateCallback>,
+}
+
+impl CookieRotator {
+    pub(crate) fn new(
+        http: reqwest::Client,
+        url: Url,
+        cookies: Cookies,
+        on_rotated: Option<RotateCallback>,
+    ) -> Self {
+        Self {
+            http,
+            url,
+            cookies: RwLock::new(cookies),
+            last_rotation: Mutex::new(None),
+            on_rotated,
+        }
+    }
+
+    pub(crate) fn cookies(&self) -> Cookies {
+        self.cookies.read().unwrap().clone()
+    }
+
+    pub(crate) fn header(&self) -> Result<HeaderValue> {
+        self.cookies.read().unwrap().header()
+    }
+
+    /// Asks Google for a fresh `__Secure-1PSIDTS`.
+    ///
+    /// Returns `false` without a request when the last rotation was too recent.
+    pub(crate) async fn rotate(&self) -> Result<bool> {
+        {
+            let mut last = self.last_rotation.lock().unwrap();
+            if last.is_some_and(|at| at.elapsed() < MIN_ROTATE_GAP) {
+                return Ok(false);
+            }
+            *last = Some(Instant::now());
+        }
+
+        let resp = self
+            .http
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(COOKIE, self.header()?)
+            .body(r#"[000,"-0000000000000000000"]"#)
+            .send()
+            .await?;
+
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(GeminiError::InvalidCookies(
+                "Google refused to rotate the cookies, log in again".to_string(),
+            ));
+        }
+        let resp = resp.error_for_status()?;
+
+        let psidts = resp
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next()?.split_once('='))
+            .find(|(name, _)| name.trim() == PSIDTS)
+            .map(|(_, value)| value.trim().to_string());
+
+        let Some(psidts) = psidts else {
+            return Ok(false);
+        };
+
+        self.cookies.write().unwrap().set(PSIDTS, psidts.as_str());
+        if let Some(on_rotated) = &self.on_rotated {
+            on_rotated(&psidts);
+        }
+        Ok(true)
+    }
+
+    /// Rotates every `interval` until the returned task is aborted.
+    pub(crate) fn spawn_timer(self: &Arc<Self>, interval: Duration) -> RotationTask {
+        let rotator = Arc::clone(self);
+        let handle = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            // the first tick completes immediately
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                // a failed rotation is retried on the next tick or on the next auth failure
+                let _ = rotator.rotate().await;
+            }
+        });
+        RotationTask(handle)
+    }
+}
+
+/// Background rotation, stopped when the client is dropped.
+pub(crate) struct RotationTask(tokio::task::JoinHandle<()>);
+
+impl Drop for RotationTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
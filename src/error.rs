@@ -0,0 +1,130 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Longest part of a raw payload kept in [`GeminiError::UnexpectedPayload`].
+const SNIPPET_LEN: usize = 200;
+
+/// Everything that can go wrong while talking to Gemini.
+#[derive(Debug)]
+pub enum GeminiError {
+    /// Google served a CAPTCHA page instead of Gemini.
+    Captcha,
+    /// Google answered with `429 Too Many Requests`.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// Google answered with a `5xx` status.
+    ServerError {
+        status: u16,
+        retry_after: Option<Duration>,
+    },
+    /// The cookies were rejected or can't be sent as a header.
+    InvalidCookies(String),
+    /// A builder option has an unusable value.
+    InvalidConfig(String),
+    /// The homepage didn't contain the `SNlM0e` token.
+    TokenNotFound,
+    /// The response didn't have the shape the parser expects.
+    UnexpectedPayload {
+        reason: String,
+        snippet: String,
+    },
+    Http(reqwest::Error),
+    Io(std::io::Error),
+}
+
+impl GeminiError {
+    /// `true` when Google refused the request before acting on it, so sending
+    /// it again can't post the same prompt twice.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Captcha | Self::RateLimited { .. } | Self::ServerError { .. } => true,
+            // the request never reached the server
+            Self::Http(e) => e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// How long the server asked to wait with `Retry-After`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after } | Self::ServerError { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// Stable snake_case name of the variant, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Captcha => "captcha",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::ServerError { .. } => "server_error",
+            Self::InvalidCookies(_) => "invalid_cookies",
+            Self::InvalidConfig(_) => "invalid_config",
+            Self::TokenNotFound => "token_not_found",
+            Self::UnexpectedPayload { .. } => "unexpected_payload",
+            Self::Http(_) => "http",
+            Self::Io(_) => "io",
+        }
+    }
+
+    pub(crate) fn unexpected_payload(reason: impl Into<String>, raw: &str) -> Self {
+        let snippet = match raw.char_indices().nth(SNIPPET_LEN) {
+            Some((end, _)) => format!("{}...", &raw[..end]),
+            None => raw.to_string(),
+        };
+        Self::UnexpectedPayload {
+            reason: reason.into(),
+            snippet,
+        }
+    }
+}
+
+impl fmt::Display for GeminiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Captcha => write!(
+                f,
+                "Google detected it as a malicious action. The block will expire shortly after those requests stop. Try again later."
+            ),
+            Self::RateLimited { .. } => {
+                write!(f, "Too many requests. Wait a bit and try again.")
+            }
+            Self::ServerError { status, .. } => write!(f, "Gemini is unavailable ({status})"),
+            Self::InvalidCookies(reason) => write!(f, "Invalid cookies: {reason}"),
+            Self::InvalidConfig(reason) => write!(f, "Invalid configuration: {reason}"),
+            Self::TokenNotFound => write!(f, "SNlM0e not found. Check your cookies."),
+            Self::UnexpectedPayload { reason, snippet } => {
+                write!(f, "Unexpected response from Gemini: {reason} ({snippet})")
+            }
+            Self::Http(e) => write!(f, "HTTP error: {e}"),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for GeminiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for GeminiError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl From<std::io::Error> for GeminiError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+pub type Result<T, E = GeminiError> = std::result::Result<T, E>;
@@ -0,0 +1,48 @@
+//! Google Gemini (previously Bard) client, driven by your browser session cookies.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut client = bard_rs::GeminiClient::builder()
+//!     .psid("g.a000...")
+//!     .psidts("sidts-...")
+//!     .build()
+//!     .await?;
+//!
+//! let response = client.ask("Hello, Gemini!").await?;
+//! println!("{}", response.content);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The `server`, `mcp`, `markdown` and `sqlite` features, on by default, add
+//! what the `bard-rs` binary needs on top of the client: the HTTP and MCP
+//! servers, terminal formatting and reading Firefox's `cookies.sqlite`.
+
+pub mod batch;
+pub mod client;
+pub mod config;
+pub mod cookies;
+pub mod error;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+#[cfg(feature = "mcp")]
+pub mod mcp;
+pub mod parser;
+pub mod ratelimit;
+pub mod response;
+pub mod retry;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod session;
+pub mod stream;
+pub mod upload;
+
+pub use client::{GeminiClient, GeminiClientBuilder};
+pub use cookies::Cookies;
+pub use error::GeminiError;
+pub use ratelimit::{RateLimit, RateLimiter};
+pub use response::{ConversationIds, Draft, GeminiResponse, Image, ImageKind, Location};
+pub use retry::RetryPolicy;
+pub use session::Session;
+pub use stream::StreamEvent;
+pub use upload::Attachment;
@@ -1,22 +1,29 @@
 use futures_util::io::AsyncWriteExt;
 
-use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::Arc;
 use std::time::Duration;
 
 use chrono::Local;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
+use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
-use rand::Rng;
-use regex::Regex;
-use reqwest::header::{HeaderMap, HeaderValue, COOKIE, USER_AGENT};
-use serde_json::{json, Value};
-use url::form_urlencoded;
 
-use rustyline_async::{Readline, ReadlineEvent, SharedWriter};
+use bard_rs::config::{Config, Profile};
+use bard_rs::cookies::PSID;
+use bard_rs::markdown::{self, CodeBlock, MarkdownStream};
+use bard_rs::server::Backend;
+use bard_rs::{
+    ConversationIds, Cookies, GeminiClient, GeminiError, GeminiResponse, Image, ImageKind,
+    RateLimit, RateLimiter, RetryPolicy, Session, StreamEvent,
+};
+use rustyline_async::{Readline, ReadlineError, ReadlineEvent};
+use serde::Serialize;
 
 // const LOADING_CHARS: &str = "/-\\|/-\\|";
 
@@ -58,259 +65,368 @@ struct Args {
     /// Proxy
     #[arg(short = 'x', long, help = "Proxy server", default_value = "")]
     proxy: String,
+
+    /// Base URL
+    #[arg(
+        long,
+        help = "Gemini web app URL, e.g. a mock server or a reverse proxy (GEMINI_BASE_URL)",
+        default_value = ""
+    )]
+    base_url: String,
+
+    /// Retries
+    #[arg(
+        long,
+        help = "How many times to retry a prompt Google rejected (429, CAPTCHA, 5xx), 3 by default"
+    )]
+    retries: Option<u32>,
+
+    /// Language
+    #[arg(long, help = "Language Gemini answers in, e.g. en or ko")]
+    language: Option<String>,
+
+    /// Config profile
+    #[arg(long, help = "Profile of the config file to use")]
+    profile: Option<String>,
+
+    /// Config file
+    #[arg(
+        long,
+        help = "Config file, ~/.config/bard-rs/config.toml by default (GEMINI_CONFIG)"
+    )]
+    config: Option<PathBuf>,
+
+    /// Markdown rendering
+    #[arg(
+        long,
+        help = "Print answers as raw markdown instead of formatting them (GEMINI_RENDER=false)"
+    )]
+    no_render: bool,
+
+    /// Cookies export
+    #[arg(
+        long,
+        help = "Netscape cookies.txt or Firefox cookies.sqlite to take the Google cookies from"
+    )]
+    cookies_file: Option<PathBuf>,
+
+    /// Cookie rotation
+    #[arg(
+        long,
+        help = "Write the rotated __Secure-1PSIDTS back to the .env file as PSIDTS (config profiles are not updated)"
+    )]
+    save_rotated_cookies: bool,
+
+    /// Session
+    #[arg(long, help = "Resume a conversation saved with !save-session <name>")]
+    resume: Option<String>,
+
+    /// Piped prompt prefix
+    #[arg(
+        long,
+        help = "Text sent before the piped stdin, e.g. \"Explain this log:\""
+    )]
+    prompt: Option<String>,
+
+    /// Output format
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Print answers as text or as JSON, one object per answer (json is pretty-printed)"
+    )]
+    output: OutputFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-struct Chatbot {
-    client: reqwest::Client,
-    reqid: u64,
-    snlm0e: String,
-    conversation_id: String,
-    response_id: String,
-    choice_id: String,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Jsonl,
 }
 
-impl Chatbot {
-    pub async fn new(_1psid: &str, _1psidts: &str) -> Result<Self, Box<dyn Error>> {
-        let cookie = format!("__Secure-1PSID={_1psid}; __Secure-1PSIDTS={_1psidts}");
-        // println!("{}", cookie);
+impl OutputFormat {
+    /// `value` as JSON, on a single line for `jsonl`.
+    fn render(self, value: &impl Serialize) -> String {
+        let json = match self {
+            OutputFormat::Json => serde_json::to_string_pretty(value),
+            _ => serde_json::to_string(value),
+        };
+        json.expect("answers always serialize")
+    }
+}
 
-        let mut headers = HeaderMap::new();
-        headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36"));
-        headers.insert(COOKIE, HeaderValue::from_str(&cookie)?);
+/// `{"error": {"kind": ..., "message": ...}}`, printed in place of an answer.
+fn error_json(err: &GeminiError) -> serde_json::Value {
+    serde_json::json!({
+        "error": {
+            "kind": err.kind(),
+            "message": err.to_string(),
+        }
+    })
+}
 
-        let client_builder = match env::var("GEMINI_PROXY_SERVER") {
-            Ok(proxy_server) if !proxy_server.is_empty() => reqwest::Client::builder()
-                .default_headers(headers)
-                .proxy(reqwest::Proxy::all(&proxy_server)?),
-            _ => reqwest::Client::builder().default_headers(headers),
-        };
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send one prompt, print the answer to stdout and exit
+    Ask(AskArgs),
+    /// Answer every prompt of a JSONL file, appending the results to another
+    Batch(BatchArgs),
+    /// Serve OpenAI- and Ollama-compatible APIs backed by the Gemini session
+    Serve(ServeArgs),
+    /// Run an MCP server on stdin/stdout, offering Gemini as a tool to other agents
+    Mcp,
+}
 
-        let client = client_builder.build()?;
+#[derive(clap::Args, Debug)]
+struct AskArgs {
+    /// Prompt to send
+    prompt: String,
 
-        // 1. GET request to https://gemini.google.com/
-        let resp = client.get("https://gemini.google.com/").send().await?;
-        let body = resp.text().await?;
+    /// Conversation to continue
+    #[arg(long)]
+    conversation_id: Option<String>,
 
-        // println!("{:#?}", body);
+    /// Last answer of the conversation to continue
+    #[arg(long)]
+    response_id: Option<String>,
 
-        // 2. Check if the body contains the word "CAPTCHA"
-        if body.contains("CAPTCHA") {
-            panic!(
-                "ERROR: Google detected it as a malicious action. The block will expire shortly after those requests stop. Try again later."
-            );
-        }
+    /// Draft of the last answer to continue from
+    #[arg(long)]
+    choice_id: Option<String>,
 
-        // 2. Extract SNlM0e value using regex
-        let re = Regex::new(r#"SNlM0e":"(.*?)""#).unwrap();
-        // println!("Extracting SNlM0e {:#?}", re);
-        let snlm0e = re
-            .captures(&body)
-            .and_then(|caps| caps.get(1).map(|m| m.as_str()))
-            .expect("SNlM0e not found. Check your cookies.");
-
-        let reqid: u64 = rand::thread_rng().gen_range(100000..999999);
-
-        Ok(Self {
-            client,
-            reqid,
-            snlm0e: snlm0e.to_owned(),
-            conversation_id: String::new(),
-            response_id: String::new(),
-            choice_id: String::new(),
-        })
-    }
+    /// Image to ask about, can be repeated
+    #[arg(long = "image", value_name = "PATH")]
+    images: Vec<PathBuf>,
 
-    async fn ask(
-        &mut self,
-        message: &str,
-        loading_chars: &str,
-        writer: &mut SharedWriter,
-    ) -> Result<HashMap<String, Value>, Box<dyn Error>> {
-        let progress_bar = ProgressBar::new(100);
-        // let tick_chars = "⠁⠂⠄⡀⢀⠠⠐⠈ ";
-        // let tick_chars = "○○◔◔◑◑◕◕●●◕◕◑◑◔◔ ";
-        // let tick_chars = "▁▁▂▂▃▃▄▄▅▅▆▆▇▇██▇▇▆▆▅▅▄▄▃▃▂▂ ";
-        // let tick_chars = "-\\|/-\\|/";
-        // let tick_chars = "◐◐◓◓◑◑◒◒";
-        // let tick_chars = "/-\\|/-\\|";
-        writer.write_all(b"\r\x1b[2K").await?; // Clear the line after the progress bar
-
-        progress_bar.set_style(
-            ProgressStyle::with_template(
-                // "{spinner:.cyan} [{elapsed_precise}] [{wide_bar}] ({percent}%)",
-                "[ {spinner:.cyan} {spinner:.red} {spinner:.yellow} {spinner:.green} ] ({percent}% | {elapsed_precise})",
-            )
-                .unwrap()
-                .tick_chars(loading_chars),
-        );
+    /// Print the options continuing the conversation to stderr
+    #[arg(long)]
+    print_ids: bool,
 
-        progress_bar.enable_steady_tick(Duration::from_millis(100));
-        progress_bar.set_draw_target(ProgressDrawTarget::stdout_with_hz(20)); // redraws at most 20 times per second
-        progress_bar.set_position(10u64);
-
-        // 3. Send POST request
-        let message_struct = json!([
-            [message],
-            (),
-            [self.conversation_id, self.response_id, self.choice_id],
-        ]);
-        let form_data = json!([(), message_struct.to_string()]).to_string();
-
-        let body_data = format!(
-            "f.req={}&at={}&",
-            urlencoding::encode(&form_data),
-            urlencoding::encode(&self.snlm0e)
-        );
+    /// Don't show the spinner on stderr
+    #[arg(long)]
+    no_spinner: bool,
+}
 
-        let encoded: String = form_urlencoded::Serializer::new("https://gemini.google.com/_/BardChatUi/data/assistant.lamda.BardFrontendService/StreamGenerate?".to_string())
-            .append_pair("bl", "boq_assistant-bard-web-server_20240717.08_p5")
-            .append_pair("_reqid", &self.reqid.to_string())
-            .append_pair("rt", "c")
-            // .append_pair("hl", "en")
-            .finish();
-
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "Content-Type",
-            HeaderValue::from_static("application/x-www-form-urlencoded;charset=UTF-8"),
-        );
-        headers.insert(
-            "Origin",
-            HeaderValue::from_static("https://gemini.google.com"),
-        );
-        headers.insert(
-            "Referer",
-            HeaderValue::from_static("https://gemini.google.com/"),
-        );
+#[derive(clap::Args, Debug)]
+struct BatchArgs {
+    /// JSONL file, one {"prompt": ..., "conversation": ...} object per line
+    input: PathBuf,
 
-        progress_bar.set_position(rand::thread_rng().gen_range(20..40));
+    /// JSONL file the results are appended to, also read to resume a stopped run
+    #[arg(short, long)]
+    output: PathBuf,
 
-        let post_resp = self
-            .client
-            .post(encoded)
-            .headers(headers)
-            .body(body_data)
-            .send()
-            .await?;
+    /// Seconds to wait between two prompts
+    #[arg(long, default_value_t = 2.0)]
+    delay: f64,
 
-        progress_bar.set_position(rand::thread_rng().gen_range(60..90));
-
-        // Deserialize the JSON string
-        let text = post_resp.text().await?;
-
-        let lines: Vec<&str> = text.split('\n').collect();
-        let json_str = lines[3];
-
-        let data: Result<Vec<Vec<Value>>, serde_json::Error> = serde_json::from_str(json_str);
-        let chat_data = data
-            .as_ref()
-            .ok()
-            .and_then(|inner_data| inner_data.get(0).and_then(|item| item.get(2)));
-
-        let mut results: HashMap<String, Value> = HashMap::new();
-
-        if let Some(chat_data) = chat_data {
-            if let Value::String(chat_data_str) = chat_data {
-                let json_chat_data: Vec<Value> = serde_json::from_str(chat_data_str)?;
-
-                // println!("{:#?}", json_chat_data);
-
-                results.insert("content".to_string(), json_chat_data[4][0][1][0].clone());
-                results.insert("conversation_id".to_string(), json_chat_data[1][0].clone());
-                results.insert("response_id".to_string(), json_chat_data[1][1].clone());
-                // factualityQueries is now null, so I've removed that line
-                results.insert("textQuery".to_string(), json_chat_data[2][0][0].clone());
-
-                let choices: Vec<HashMap<&str, &Value>> = json_chat_data[4]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .skip(1) // skip first answer as default
-                    .map(|choice| {
-                        let mut choice_map = HashMap::new();
-                        choice_map.insert("id", &choice[0]);
-                        choice_map.insert("content", &choice[1][0]);
-                        choice_map
-                    })
-                    .collect();
-
-                results.insert("choices".to_string(), serde_json::json!(choices));
-
-                // Let's also extract the location information
-                if let Some(location) = json_chat_data.get(7) {
-                    let mut location_map = HashMap::new();
-                    if let Value::String(loc_str) = &location[0] {
-                        location_map.insert("address".to_string(), loc_str.clone());
-                    }
-                    if let Value::String(loc_str) = &location[1] {
-                        location_map.insert("place_type".to_string(), loc_str.clone());
-                    }
-                    results.insert("location".to_string(), serde_json::json!(location_map));
-                }
+    /// Start over, discarding the results already in the output file
+    #[arg(long)]
+    fresh: bool,
+}
 
-                let conversation_id = results.get("conversation_id").and_then(Value::as_str);
-                let response_id = results.get("response_id").and_then(Value::as_str);
-                let mut choice_id = results
-                    .get("choices")
-                    .and_then(Value::as_array)
-                    .and_then(|choices| choices.get(0))
-                    .and_then(|choice| choice.get("id"))
-                    .and_then(Value::as_str)
-                    .map(|s| s.to_string());
-
-                // sometimes, there is only one choice.
-                // If not found, search for an element that starts with "rc_"
-                if choice_id.is_none() {
-                    'outer: for item in json_chat_data.iter() {
-                        if let Some(array) = item.as_array() {
-                            for sub_item in array.iter() {
-                                if let Some(s) = sub_item.as_str() {
-                                    if s.starts_with("rc_") {
-                                        choice_id = Some(s.to_string());
-                                        break 'outer;
-                                    }
-                                } else if let Some(array) = sub_item.as_array() {
-                                    for inner_item in array.iter() {
-                                        if let Some(s) = inner_item.as_str() {
-                                            if s.starts_with("rc_") {
-                                                choice_id = Some(s.to_string());
-                                                break 'outer;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
 
-                if let (Some(conversation_id), Some(response_id), Some(choice_id)) =
-                    (conversation_id, response_id, choice_id)
-                {
-                    self.conversation_id = conversation_id.to_owned();
-                    self.response_id = response_id.to_owned();
-                    self.choice_id = choice_id.to_owned();
-                    self.reqid += 100000;
-                    progress_bar.set_position(100u64);
-                } else {
-                    eprintln!("Error: couldn't get conversation_id, response_id or choice_id");
-                }
-            } else {
-                eprintln!("Error: chat_data is not a string");
+    /// Key clients must send as `Authorization: Bearer <key>` (GEMINI_SERVE_API_KEY)
+    #[arg(long)]
+    api_key: Option<String>,
+}
+
+/// Where the REPL writes everything but JSON answers.
+type Writer = Box<dyn futures_util::AsyncWrite + Unpin + Send>;
+
+/// Where the REPL reads lines from.
+enum Input {
+    /// Line editing and history, drawn on stdout
+    Terminal(Readline),
+    /// Plain lines, prompting on stderr, so stdout only carries JSON answers
+    Plain(tokio::io::Lines<tokio::io::BufReader<tokio::io::Stdin>>),
+}
+
+impl Input {
+    async fn readline(&mut self) -> Result<ReadlineEvent, ReadlineError> {
+        match self {
+            Input::Terminal(readline) => readline.readline().await,
+            Input::Plain(lines) => {
+                eprint!(">- ");
+                // a broken stdin ends the session like EOF
+                Ok(match lines.next_line().await {
+                    Ok(Some(line)) => ReadlineEvent::Line(line),
+                    Ok(None) | Err(_) => ReadlineEvent::Eof,
+                })
             }
-        } else {
-            eprintln!("Error: chat_data not found");
         }
+    }
 
-        progress_bar.finish_and_clear();
+    fn add_history_entry(&mut self, entry: String) {
+        if let Input::Terminal(readline) = self {
+            readline.add_history_entry(entry);
+        }
+    }
 
-        Ok(results)
+    fn flush(&mut self) -> Result<(), ReadlineError> {
+        match self {
+            Input::Terminal(readline) => readline.flush(),
+            Input::Plain(_) => Ok(()),
+        }
     }
+}
+
+fn start_progress_bar(loading_chars: &str, target: ProgressDrawTarget) -> ProgressBar {
+    let progress_bar = ProgressBar::new_spinner();
+    // let tick_chars = "⠁⠂⠄⡀⢀⠠⠐⠈ ";
+    // let tick_chars = "○○◔◔◑◑◕◕●●◕◕◑◑◔◔ ";
+    // let tick_chars = "▁▁▂▂▃▃▄▄▅▅▆▆▇▇██▇▇▆▆▅▅▄▄▃▃▂▂ ";
+    // let tick_chars = "-\\|/-\\|/";
+    // let tick_chars = "◐◐◓◓◑◑◒◒";
+    // let tick_chars = "/-\\|/-\\|";
+    progress_bar.set_style(
+        ProgressStyle::with_template(
+            // "{spinner:.cyan} [{elapsed_precise}] [{wide_bar}] ({percent}%)",
+            "[ {spinner:.cyan} {spinner:.red} {spinner:.yellow} {spinner:.green} ] ({elapsed_precise})",
+        )
+            .unwrap()
+            .tick_chars(loading_chars),
+    );
+
+    progress_bar.enable_steady_tick(Duration::from_millis(100));
+    progress_bar.set_draw_target(target);
+    progress_bar
+}
+
+/// Prints the answer under `header` as it is generated, formatted a block at a time with `render`.
+/// The spinner only runs until the first words arrive, or until the whole
+/// answer is in when there is no `header` to print it under.
+async fn stream_answer(
+    chatbot: &mut GeminiClient,
+    message: &str,
+    loading_chars: &str,
+    header: Option<&str>,
+    render: bool,
+    writer: &mut Writer,
+) -> Result<GeminiResponse, GeminiError> {
+    // without a header the answer is printed as JSON, nothing else may reach stdout
+    let target = if header.is_some() {
+        // Clear the line after the progress bar
+        writer.write_all(b"\r\x1b[2K").await?;
+        // redraws at most 20 times per second
+        ProgressDrawTarget::stdout_with_hz(20)
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    let progress_bar = start_progress_bar(loading_chars, target);
 
-    fn reset(&mut self) {
-        self.conversation_id.clear();
-        self.response_id.clear();
-        self.choice_id.clear();
+    let stream = match chatbot.ask_stream(message).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            progress_bar.finish_and_clear();
+            return Err(e);
+        }
+    };
+    let mut stream = pin!(stream);
+    let mut markdown = render.then(|| MarkdownStream::new(answer_width()));
+    let mut printed = String::new();
+
+    let mut started = false;
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(StreamEvent::Delta(delta)) => {
+                let Some(header) = header else {
+                    continue;
+                };
+                printed.push_str(&delta);
+                let delta = match &mut markdown {
+                    Some(markdown) => markdown.push(&delta),
+                    None => delta,
+                };
+                if delta.is_empty() {
+                    continue;
+                }
+                if !started {
+                    progress_bar.finish_and_clear();
+                    writer.write_all(header.as_bytes()).await?;
+                    started = true;
+                }
+                writer.write_all(delta.as_bytes()).await?;
+            }
+            Ok(StreamEvent::Done(response)) => {
+                progress_bar.finish_and_clear();
+                if let Some(header) = header {
+                    let rest = finish_answer(
+                        &printed,
+                        &response.content,
+                        markdown.as_mut(),
+                        answer_width(),
+                    );
+                    if !started {
+                        writer.write_all(header.as_bytes()).await?;
+                    }
+                    writer.write_all(rest.as_bytes()).await?;
+                }
+                return Ok(response);
+            }
+            Err(e) => {
+                progress_bar.finish_and_clear();
+                if started {
+                    writer.write_all(b"\n").await?;
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    progress_bar.finish_and_clear();
+    Err(GeminiError::UnexpectedPayload {
+        reason: "the answer ended early".to_string(),
+        snippet: String::new(),
+    })
+}
+
+/// [`stream_answer`], waiting out rate limits and outages with a countdown.
+async fn stream_answer_with_retry(
+    chatbot: &mut GeminiClient,
+    message: &str,
+    loading_chars: &str,
+    header: Option<&str>,
+    render: bool,
+    retry_policy: &RetryPolicy,
+    writer: &mut Writer,
+) -> Result<GeminiResponse, GeminiError> {
+    let mut retry = 0;
+    loop {
+        match stream_answer(chatbot, message, loading_chars, header, render, writer).await {
+            Err(e) => match retry_policy.delay_for(retry, &e) {
+                Some(delay) => {
+                    retry += 1;
+                    let mut remaining = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+                    while remaining > 0 {
+                        writer
+                            .write_all(
+                                format!(
+                                    "\r\x1b[2K{} {e} Retrying in {remaining}s ({retry}/{})",
+                                    "╰─>".bright_yellow(),
+                                    retry_policy.max_retries
+                                )
+                                .as_bytes(),
+                            )
+                            .await?;
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        remaining -= 1;
+                    }
+                    writer.write_all(b"\r\x1b[2K").await?;
+                }
+                None => return Err(e),
+            },
+            result => return result,
+        }
     }
 }
 
@@ -326,6 +442,58 @@ async fn append_to_file(file_path: &PathBuf, content: &str) -> Result<(), Box<dy
     Ok(())
 }
 
+/// Swaps the last answer recorded in the transcript for `new` (after `!choose`).
+async fn replace_last_answer(
+    file_path: &PathBuf,
+    old: &str,
+    new: &str,
+) -> Result<(), Box<dyn Error>> {
+    let transcript = match tokio::fs::read_to_string(file_path).await {
+        Ok(transcript) => transcript,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let old = format!("**Gemini**: {}\n\n", old);
+    if let Some(start) = transcript.rfind(&old) {
+        let mut transcript = transcript;
+        transcript.replace_range(
+            start..start + old.len(),
+            &format!("**Gemini**: {}\n\n", new),
+        );
+        tokio::fs::write(file_path, transcript).await?;
+    }
+    Ok(())
+}
+
+/// Sets `key` in a .env file, keeping every other line as is.
+fn update_env_file(path: &PathBuf, key: &str, value: &str) -> std::io::Result<()> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let prefix = format!("{key}=");
+    let mut found = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with(&prefix) {
+                found = true;
+                format!("{prefix}{value}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        lines.push(format!("{prefix}{value}"));
+    }
+
+    std::fs::write(path, lines.join("\n") + "\n")
+}
+
 // Function to encapsulate the repeated logic
 fn get_env_var_or_dotenv(var_name: &str) -> Option<String> {
     env::var(var_name)
@@ -346,42 +514,651 @@ fn get_env_var_or_dotenv(var_name: &str) -> Option<String> {
         })
 }
 
-fn strip_ansi_codes(s: &str) -> String {
-    let re = regex::Regex::new(r"\x1b\[[0-9;]*m").unwrap();
-    re.replace_all(s, "").to_string()
+fn parse_env_var<T: std::str::FromStr>(var_name: &str) -> Option<T> {
+    get_env_var_or_dotenv(var_name).and_then(|value| value.trim().parse().ok())
+}
+
+/// Everything the CLI can be configured with.
+/// Each value comes from the CLI, the environment, the config profile or the default, in that order.
+struct Settings {
+    psid: String,
+    psidts: String,
+    cookies_file: Option<PathBuf>,
+    proxy: String,
+    base_url: String,
+    history_path: String,
+    spinner: String,
+    multi: bool,
+    /// Format answers as markdown, only when stdout is a terminal
+    render: bool,
+    language: String,
+    retries: u32,
+    rate_limit: RateLimit,
+}
+
+impl Settings {
+    fn resolve(args: &Args, profile: Profile) -> Self {
+        // empty values, like the ones of .env_sample, count as unset
+        fn pick(cli: Option<&String>, var_name: &str, profile: Option<String>) -> Option<String> {
+            let is_set = |value: &String| !value.trim().is_empty();
+            cli.filter(|value| is_set(value))
+                .cloned()
+                .or_else(|| get_env_var_or_dotenv(var_name).filter(is_set))
+                .or(profile.filter(is_set))
+        }
+        let default_limit = RateLimit::default();
+        let path_string = |path: Option<PathBuf>| path.map(|p| p.to_string_lossy().into_owned());
+
+        Self {
+            psid: pick(args.psid.as_ref(), "PSID", profile.psid).unwrap_or_default(),
+            psidts: pick(args.psidts.as_ref(), "PSIDTS", profile.psidts).unwrap_or_default(),
+            cookies_file: pick(
+                path_string(args.cookies_file.clone()).as_ref(),
+                "GEMINI_COOKIES_FILE",
+                path_string(profile.cookies_file),
+            )
+            .map(PathBuf::from),
+            proxy: pick(Some(&args.proxy), "GEMINI_PROXY_SERVER", profile.proxy)
+                .unwrap_or_default(),
+            base_url: pick(Some(&args.base_url), "GEMINI_BASE_URL", profile.base_url)
+                .unwrap_or_default(),
+            history_path: pick(
+                Some(&args.path),
+                "GEMINI_HISTORY",
+                path_string(profile.history_dir),
+            )
+            .unwrap_or_default(),
+            spinner: pick(None, "GEMINI_SPINNER", profile.spinner)
+                .unwrap_or_else(|| "/-\\|/-\\|".to_string()),
+            multi: args.multi
+                || parse_env_var("GEMINI_MULTI")
+                    .or(profile.multi)
+                    .unwrap_or(false),
+            render: !args.no_render
+                && parse_env_var("GEMINI_RENDER")
+                    .or(profile.render)
+                    .unwrap_or(true)
+                && std::io::stdout().is_terminal(),
+            language: pick(args.language.as_ref(), "GEMINI_LANGUAGE", profile.language)
+                .unwrap_or_default(),
+            retries: args
+                .retries
+                .or_else(|| parse_env_var("GEMINI_RETRIES"))
+                .or(profile.retries)
+                .unwrap_or(3),
+            rate_limit: RateLimit {
+                requests_per_minute: parse_env_var("GEMINI_RATE_LIMIT_RPM")
+                    .or(profile.rate_limit_rpm)
+                    .unwrap_or(default_limit.requests_per_minute),
+                burst: parse_env_var("GEMINI_RATE_LIMIT_BURST")
+                    .or(profile.rate_limit_burst)
+                    .unwrap_or(default_limit.burst),
+                min_gap: parse_env_var("GEMINI_RATE_LIMIT_MIN_GAP_MS")
+                    .or(profile.rate_limit_min_gap_ms)
+                    .map(Duration::from_millis)
+                    .unwrap_or(default_limit.min_gap),
+            },
+        }
+    }
+}
+
+/// Reads the config profile and resolves the settings.
+fn load_settings(args: &Args) -> Result<Settings, GeminiError> {
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| get_env_var_or_dotenv("GEMINI_CONFIG").map(PathBuf::from))
+        .or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let profile_name = args
+        .profile
+        .clone()
+        .or_else(|| get_env_var_or_dotenv("GEMINI_PROFILE"));
+    Ok(Settings::resolve(
+        args,
+        config.profile(profile_name.as_deref())?,
+    ))
+}
+
+/// Opens a Gemini session, retrying rejected prompts as `retry_policy` says.
+async fn build_chatbot(
+    args: &Args,
+    settings: &Settings,
+    retry_policy: RetryPolicy,
+) -> Result<GeminiClient, GeminiError> {
+    let cookies = match &settings.cookies_file {
+        Some(path) => Cookies::from_file(path).map_err(|e| {
+            GeminiError::InvalidCookies(format!("couldn't read {}: {e}", path.display()))
+        })?,
+        None => Cookies::new(),
+    };
+    if settings.psid.is_empty() && cookies.get(PSID).is_none() {
+        return Err(GeminiError::InvalidCookies(
+            "No session ID provided. Either pass it with -s, --cookies-file, a config profile or provide a .env file".to_string(),
+        ));
+    }
+
+    // Every conversation of the process draws from the same bucket
+    RateLimiter::shared().set_limit(settings.rate_limit);
+
+    let mut builder = GeminiClient::builder()
+        .psid(&settings.psid)
+        .psidts(&settings.psidts)
+        .cookies(cookies)
+        .proxy(&settings.proxy)
+        .base_url(&settings.base_url)
+        .language(&settings.language)
+        .rotate_cookies_url(get_env_var_or_dotenv("GEMINI_ROTATE_COOKIES_URL").unwrap_or_default())
+        .build_label(get_env_var_or_dotenv("GEMINI_BUILD_LABEL").unwrap_or_default())
+        .upload_url(get_env_var_or_dotenv("GEMINI_UPLOAD_URL").unwrap_or_default())
+        .retry_policy(retry_policy);
+
+    if args.save_rotated_cookies {
+        let env_path = if !args.env.is_empty() {
+            PathBuf::from(&args.env)
+        } else {
+            PathBuf::from(".env")
+        };
+        builder = builder.on_cookies_rotated(move |psidts| {
+            if let Err(e) = update_env_file(&env_path, "PSIDTS", psidts) {
+                eprintln!(
+                    "{} Couldn't save the rotated cookie: {e}",
+                    "ERROR:".bright_red()
+                );
+            }
+        });
+    }
+
+    builder.build().await
+}
+
+/// Arguments of the REPL command `command` when `input` is that command,
+/// alone or followed by whitespace, so `!code` doesn't also match `!codex`.
+fn command_args<'a>(input: &'a str, command: &str) -> Option<&'a str> {
+    input
+        .strip_prefix(command)
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// Code blocks of every draft of `response`, with the draft number `!show` gives it.
+fn answer_code_blocks(response: &GeminiResponse) -> Vec<(usize, CodeBlock)> {
+    if response.drafts.is_empty() {
+        return markdown::code_blocks(&response.content)
+            .into_iter()
+            .map(|block| (1, block))
+            .collect();
+    }
+    response
+        .drafts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, draft)| {
+            markdown::code_blocks(&draft.text)
+                .into_iter()
+                .map(move |block| (i + 1, block))
+        })
+        .collect()
+}
+
+/// Numbered list of `blocks`, as `!code save N` counts them.
+fn list_code_blocks(blocks: &[(usize, CodeBlock)], arrow: &str) -> String {
+    let several_drafts = blocks.iter().any(|(draft, _)| *draft > 1);
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, (draft, block))| {
+            let language = if block.language.is_empty() {
+                "text"
+            } else {
+                &block.language
+            };
+            let lines = block.code.lines().count();
+            let first = block
+                .code
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or("")
+                .trim();
+            let draft = if several_drafts {
+                format!(", draft {draft}")
+            } else {
+                String::new()
+            };
+            format!(
+                "{}. {language}{draft}, {lines} line{}: {first}",
+                i + 1,
+                if lines == 1 { "" } else { "s" }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(&format!("\n{arrow} "))
+}
+
+/// Writes `block` to `path`, adding the extension of its language when `path`
+/// has none, or inside `path` as `block_N` when it is a directory.
+async fn save_code_block(block: &CodeBlock, path: &Path, n: usize) -> std::io::Result<PathBuf> {
+    let mut path = if path.is_dir() || path.as_os_str().to_string_lossy().ends_with('/') {
+        path.join(format!("block_{n}"))
+    } else {
+        path.to_path_buf()
+    };
+    if path.extension().is_none() {
+        path.set_extension(block.extension());
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&path, &block.code).await?;
+    Ok(path)
+}
+
+/// Numbered list of `images`, so `!images save` names the files the same way.
+fn list_images(images: &[Image], arrow: &str) -> String {
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let kind = match image.kind {
+                ImageKind::Generated => "generated",
+                ImageKind::Web => "web",
+            };
+            let alt = if image.alt.is_empty() {
+                String::new()
+            } else {
+                format!("{} ", image.alt)
+            };
+            format!("🖼  {}. {alt}({kind}) {}", i + 1, image.url)
+        })
+        .collect::<Vec<_>>()
+        .join(&format!("\n{arrow} "))
+}
+
+/// Markdown embedding the image at `target`.
+fn image_markdown(alt: &str, target: &str) -> String {
+    let alt = alt.replace(['[', ']'], "");
+    if target.contains(' ') {
+        format!("![{alt}](<{target}>)")
+    } else {
+        format!("![{alt}]({target})")
+    }
+}
+
+/// Columns left for an answer printed after `╰─> `.
+fn answer_width() -> usize {
+    markdown::terminal_width().saturating_sub(4)
+}
+
+/// `text` formatted as markdown when `render` is on, without the trailing newline.
+fn format_answer(text: &str, render: bool) -> String {
+    if render {
+        markdown::render(text, answer_width())
+            .trim_end()
+            .to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Exit status for `err`, so scripts can tell failures apart.
+fn exit_code(err: &GeminiError) -> i32 {
+    match err {
+        GeminiError::InvalidCookies(_) | GeminiError::TokenNotFound => 3,
+        GeminiError::Captcha | GeminiError::RateLimited { .. } => 4,
+        GeminiError::ServerError { .. } | GeminiError::Http(_) => 5,
+        GeminiError::UnexpectedPayload { .. } => 6,
+        GeminiError::InvalidConfig(_) | GeminiError::Io(_) => 1,
+    }
+}
+
+/// What is left to print of the final `answer` once `printed` went out as deltas,
+/// ending with a newline.
+///
+/// Gemini sometimes rewrites earlier text (citations, image placeholders) and
+/// stops sending deltas, the whole final answer is printed again then.
+fn finish_answer(
+    printed: &str,
+    answer: &str,
+    markdown: Option<&mut MarkdownStream>,
+    width: usize,
+) -> String {
+    match (answer.strip_prefix(printed), markdown) {
+        (Some(tail), Some(markdown)) => {
+            let mut rest = markdown.push(tail);
+            rest.push_str(&markdown.finish());
+            rest
+        }
+        (Some(tail), None) => format!("{tail}\n"),
+        (None, Some(_)) => format!("\n\n{}", markdown::render(answer, width)),
+        (None, None) => format!("\n\n{answer}\n"),
+    }
+}
+
+/// Prints the answer to stdout as it is generated, the spinner going to stderr.
+async fn print_answer(
+    chatbot: &mut GeminiClient,
+    message: &str,
+    render: bool,
+    progress_bar: ProgressBar,
+) -> Result<GeminiResponse, GeminiError> {
+    use std::io::Write;
+
+    let stream = match chatbot.ask_stream(message).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            progress_bar.finish_and_clear();
+            return Err(e);
+        }
+    };
+    let mut stream = pin!(stream);
+    let mut stdout = std::io::stdout();
+    let mut markdown = render.then(|| MarkdownStream::new(markdown::terminal_width()));
+    let mut printed = String::new();
+
+    let mut started = false;
+    while let Some(event) = stream.next().await {
+        progress_bar.finish_and_clear();
+        match event {
+            Ok(StreamEvent::Delta(delta)) => {
+                started = true;
+                printed.push_str(&delta);
+                let delta = match &mut markdown {
+                    Some(markdown) => markdown.push(&delta),
+                    None => delta,
+                };
+                stdout.write_all(delta.as_bytes())?;
+                stdout.flush()?;
+            }
+            Ok(StreamEvent::Done(response)) => {
+                let rest = finish_answer(
+                    &printed,
+                    &response.content,
+                    markdown.as_mut(),
+                    markdown::terminal_width(),
+                );
+                stdout.write_all(rest.as_bytes())?;
+                return Ok(response);
+            }
+            Err(e) => {
+                if started {
+                    stdout.write_all(b"\n")?;
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    progress_bar.finish_and_clear();
+    Err(GeminiError::UnexpectedPayload {
+        reason: "the answer ended early".to_string(),
+        snippet: String::new(),
+    })
+}
+
+/// `bard-rs ask`, returning the exit status.
+async fn run_ask(args: &Args, settings: &Settings, ask: &AskArgs) -> i32 {
+    let retry_policy = RetryPolicy {
+        max_retries: settings.retries,
+        ..Default::default()
+    };
+    let result = async {
+        let mut chatbot = build_chatbot(args, settings, retry_policy).await?;
+        chatbot.set_conversation_ids(ConversationIds {
+            conversation_id: ask.conversation_id.clone().unwrap_or_default(),
+            response_id: ask.response_id.clone().unwrap_or_default(),
+            choice_id: ask.choice_id.clone().unwrap_or_default(),
+        });
+        for image in &ask.images {
+            chatbot.attach_image(image).await?;
+        }
+
+        let target = if ask.no_spinner {
+            ProgressDrawTarget::hidden()
+        } else {
+            // hidden by indicatif when stderr isn't a terminal
+            ProgressDrawTarget::stderr_with_hz(20)
+        };
+        let progress_bar = start_progress_bar(&settings.spinner, target);
+        if args.output == OutputFormat::Text {
+            return print_answer(&mut chatbot, &ask.prompt, settings.render, progress_bar).await;
+        }
+        let response = chatbot.ask(&ask.prompt).await;
+        progress_bar.finish_and_clear();
+        response
+    }
+    .await;
+
+    match result {
+        Ok(response) => {
+            if args.output != OutputFormat::Text {
+                println!("{}", args.output.render(&response));
+            } else {
+                for image in &response.images {
+                    println!("{}", image_markdown(&image.alt, &image.url));
+                }
+            }
+            if ask.print_ids {
+                let ids = response.conversation_ids;
+                eprintln!(
+                    "--conversation-id {} --response-id {} --choice-id {}",
+                    ids.conversation_id, ids.response_id, ids.choice_id
+                );
+            }
+            0
+        }
+        Err(e) => {
+            if args.output != OutputFormat::Text {
+                println!("{}", args.output.render(&error_json(&e)));
+            }
+            eprintln!("{} {e}", "ERROR:".bright_red());
+            exit_code(&e)
+        }
+    }
+}
+
+/// `bard-rs batch`, returning the exit status.
+async fn run_batch(args: &Args, settings: &Settings, batch: &BatchArgs) -> i32 {
+    let retry_policy = RetryPolicy {
+        max_retries: settings.retries,
+        ..Default::default()
+    };
+    let Ok(delay) = Duration::try_from_secs_f64(batch.delay) else {
+        eprintln!("{} Invalid --delay {}", "ERROR:".bright_red(), batch.delay);
+        return 2;
+    };
+
+    let result =
+        async {
+            if batch.fresh {
+                match std::fs::remove_file(&batch.output) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            let mut chatbot = build_chatbot(args, settings, retry_policy).await?;
+            bard_rs::batch::run(&mut chatbot, &batch.input, &batch.output, delay, |record| {
+                match &record.error {
+                    None => eprintln!("{} line {}", "ok".bright_green(), record.line),
+                    Some(error) => eprintln!(
+                        "{} line {}: {}",
+                        "failed".bright_red(),
+                        record.line,
+                        error.message
+                    ),
+                }
+            })
+            .await
+        }
+        .await;
+
+    match result {
+        Ok(summary) => {
+            eprintln!(
+                "{} answered, {} failed, {} already done",
+                summary.answered, summary.failed, summary.skipped
+            );
+            if summary.failed > 0 {
+                7
+            } else {
+                0
+            }
+        }
+        Err(e) => {
+            eprintln!("{} {e}", "ERROR:".bright_red());
+            exit_code(&e)
+        }
+    }
+}
+
+/// `bard-rs serve`, returning the exit status once the server stops.
+async fn run_serve(args: &Args, settings: &Settings, serve: &ServeArgs) -> i32 {
+    let retry_policy = RetryPolicy {
+        max_retries: settings.retries,
+        ..Default::default()
+    };
+    let result = async {
+        let chatbot = build_chatbot(args, settings, retry_policy).await?;
+        let listener = tokio::net::TcpListener::bind(&serve.listen).await?;
+        let addr = listener.local_addr()?;
+        eprintln!("Serving the OpenAI API on http://{addr}/v1 and the Ollama API on http://{addr}");
+
+        let api_key = serve
+            .api_key
+            .clone()
+            .or_else(|| get_env_var_or_dotenv("GEMINI_SERVE_API_KEY"));
+        bard_rs::server::serve(Arc::new(Backend::new(chatbot)), listener, api_key).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{} {e}", "ERROR:".bright_red());
+            exit_code(&e)
+        }
+    }
+}
+
+/// `bard-rs mcp`, returning the exit status once stdin is closed.
+async fn run_mcp(args: &Args, settings: &Settings) -> i32 {
+    let retry_policy = RetryPolicy {
+        max_retries: settings.retries,
+        ..Default::default()
+    };
+    let result = async {
+        let chatbot = build_chatbot(args, settings, retry_policy).await?;
+        bard_rs::mcp::serve_stdio(Arc::new(Backend::new(chatbot))).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{} {e}", "ERROR:".bright_red());
+            exit_code(&e)
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut loading_chars = "/-\\|/-\\|";
-
     let args = Args::parse();
 
     // Load .env file if the path is provided
     if !args.env.is_empty() {
-        dotenv::from_path(args.env).ok();
+        dotenv::from_path(&args.env).ok();
     }
 
-    if !args.proxy.is_empty() {
-        env::set_var("GEMINI_PROXY_SERVER", args.proxy.as_str());
+    let settings = match load_settings(&args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{} {e}", "ERROR:".bright_red());
+            std::process::exit(exit_code(&e));
+        }
+    };
+
+    match &args.command {
+        Some(Command::Ask(ask)) => std::process::exit(run_ask(&args, &settings, ask).await),
+        Some(Command::Batch(batch)) => std::process::exit(run_batch(&args, &settings, batch).await),
+        Some(Command::Serve(serve)) => std::process::exit(run_serve(&args, &settings, serve).await),
+        Some(Command::Mcp) => std::process::exit(run_mcp(&args, &settings).await),
+        None => {}
     }
 
-    let _1psid = get_env_var_or_dotenv("PSID")
-        .expect("No session ID provided. Either pass it with -s or provide a .env file");
+    // `cat error.log | bard-rs` answers once instead of starting the REPL,
+    // unless the lines are turns of a JSON Lines session
+    let turns_on_stdin = args.output == OutputFormat::Jsonl && args.prompt.is_none();
+    if !std::io::stdin().is_terminal() && !turns_on_stdin {
+        let mut input = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut input) {
+            eprintln!("{} Couldn't read stdin: {e}", "ERROR:".bright_red());
+            std::process::exit(1);
+        }
+        let prompt = [args.prompt.as_deref().unwrap_or(""), input.trim()]
+            .into_iter()
+            .filter(|part| !part.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if prompt.is_empty() {
+            eprintln!("{} Nothing to send, stdin is empty", "ERROR:".bright_red());
+            std::process::exit(2);
+        }
 
-    let _1psidts = get_env_var_or_dotenv("PSIDTS").unwrap_or_else(|| "".to_string());
+        let ask = AskArgs {
+            prompt,
+            images: Vec::new(),
+            conversation_id: None,
+            response_id: None,
+            choice_id: None,
+            print_ids: false,
+            no_spinner: false,
+        };
+        std::process::exit(run_ask(&args, &settings, &ask).await);
+    }
 
-    // Attempt to get the path from command-line arguments or environment variable
-    let history_path = if !args.path.trim().is_empty() {
-        args.path.clone()
-    } else {
-        get_env_var_or_dotenv("GEMINI_HISTORY").unwrap_or_else(|| "".to_string())
+    // retried below, with a countdown
+    let mut chatbot = match build_chatbot(&args, &settings, RetryPolicy::none()).await {
+        Ok(chatbot) => chatbot,
+        Err(e) => {
+            eprintln!("{} {e}", "ERROR:".bright_red());
+            std::process::exit(exit_code(&e));
+        }
     };
 
-    let mut chatbot = Chatbot::new(&_1psid, &_1psidts).await?;
+    let mut loading_chars = settings.spinner.clone();
+    let mut render = settings.render;
+    let history_path = settings.history_path.clone();
+    let retry_policy = RetryPolicy {
+        max_retries: settings.retries,
+        ..Default::default()
+    };
 
     let mut first_input = true;
     let mut file_path = None;
+    let mut last_response: Option<GeminiResponse> = None;
+
+    if let Some(name) = &args.resume {
+        match Session::load(name) {
+            Ok(session) => {
+                chatbot.set_conversation_ids(session.conversation_ids);
+                // keep appending to the same transcript
+                if session.transcript_path.is_some() {
+                    first_input = false;
+                    file_path = session.transcript_path;
+                }
+                last_response = session.last_response;
+            }
+            Err(e) => {
+                eprintln!("{} Couldn't resume {name:?}: {e}", "ERROR:".bright_red());
+                std::process::exit(1);
+            }
+        }
+    }
 
     let user_prompt = "╭─ You".bright_green().to_string();
     let gemini_prompt = "╭─ Gemini".bright_cyan().to_string();
@@ -390,10 +1167,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let under_arrow_red = "╰─>".bright_red().to_string();
     let under_arrow_green = ">-"; // TODO: won't color it as it harms cursor position
 
-    let mut last_response: Option<HashMap<String, Value>> = None;
-    let (mut readline, mut writer) = Readline::new(format!("{under_arrow_green} "))?;
-    // the input line does not remain on screen after Enter
-    readline.should_print_line_on(true, true);
+    let json = args.output != OutputFormat::Text;
+    let (mut readline, mut writer): (Input, Writer) = if json {
+        let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+        let stderr = futures_util::io::AllowStdIo::new(std::io::stderr());
+        (
+            Input::Plain(tokio::io::AsyncBufReadExt::lines(stdin)),
+            Box::new(stderr),
+        )
+    } else {
+        let (mut readline, writer) = Readline::new(format!("{under_arrow_green} "))?;
+        // the input line does not remain on screen after Enter
+        readline.should_print_line_on(true, true);
+        (Input::Terminal(readline), Box::new(writer))
+    };
 
     writer.write_all(b"\n").await?;
     loop {
@@ -438,6 +1225,26 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     break;
                 } else if input == "!reset" {
                     chatbot.reset();
+                } else if let Some(name) = command_args(&input, "!save-session") {
+                    let mut session = Session {
+                        conversation_ids: chatbot.conversation_ids().clone(),
+                        transcript_path: file_path.clone(),
+                        last_response: last_response.clone(),
+                        ..Default::default()
+                    };
+                    let message = match session.save(name.trim()) {
+                        Ok(path) => format!(
+                            "Saved to {}. Continue with --resume {}",
+                            path.display(),
+                            name.trim()
+                        ),
+                        Err(e) => format!("Couldn't save the session: {e}"),
+                    };
+                    writer
+                        .write_all(
+                            format!("\n{system_prompt}\n{under_arrow_red} {message}\n").as_bytes(),
+                        )
+                        .await?;
                 } else if input == "!settings" {
                     writer
                         .write_all(format!("\n{system_prompt}\n").as_bytes())
@@ -449,7 +1256,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         )
                         .await?;
 
-                    let tick_chars = vec![
+                    let tick_chars = [
                         "⠁⠂⠄⡀⢀⠠⠐⠈",
                         "○○◔◔◑◑◕◕●●◕◕◑◑◔◔",
                         "▁▁▂▂▃▃▄▄▅▅▆▆▇▇██▇▇▆▆▅▅▄▄▃▃▂▂",
@@ -483,7 +1290,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         writer
                             .write_all(format!("Selected style: {}\n", selected_style).as_bytes())
                             .await?;
-                        loading_chars = selected_style;
+                        loading_chars = selected_style.to_string();
                     }
                 } else if input == "!show" {
                     if let Some(ref res) = last_response {
@@ -492,62 +1299,271 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         writer
                             .write_all(format!("\n\n{gemini_prompt} [{current_time}]\n").as_bytes())
                             .await?;
-                        let array = res.get("choices").unwrap().as_array().unwrap();
-
-                        for (i, object) in array.iter().enumerate() {
-                            if let Some(content) = object["content"].as_str() {
-                                writer
-                                    .write_all(
-                                        format!("{} {}. {}\n", under_arrow, i + 1, content)
-                                            .as_bytes(),
-                                    )
-                                    .await?;
+
+                        // numbered like `-m` so `!choose N` picks the draft shown as N
+                        for (i, draft) in res.drafts.iter().enumerate() {
+                            if draft.text == res.content {
+                                continue;
                             }
+                            writer
+                                .write_all(
+                                    format!(
+                                        "{} {}. {}\n",
+                                        under_arrow,
+                                        i + 1,
+                                        format_answer(&draft.text, render)
+                                    )
+                                    .as_bytes(),
+                                )
+                                .await?;
                         }
                     }
+                } else if input == "!render" {
+                    render = !render && std::io::stdout().is_terminal();
+                    let message = if render {
+                        "Answers are formatted as markdown."
+                    } else {
+                        "Answers are printed as raw markdown."
+                    };
+                    writer
+                        .write_all(
+                            format!("\n{system_prompt}\n{under_arrow_red} {message}\n").as_bytes(),
+                        )
+                        .await?;
+                } else if let Some(rest) = command_args(&input, "!code") {
+                    let blocks = last_response
+                        .as_ref()
+                        .map(answer_code_blocks)
+                        .unwrap_or_default();
+                    let args: Vec<&str> = rest.split_whitespace().collect();
+                    let message = if blocks.is_empty() {
+                        "The last answer has no code blocks.".to_string()
+                    } else {
+                        match args[..] {
+                            [] => list_code_blocks(&blocks, &under_arrow_red),
+                            ["save", n, path] => match n.parse::<usize>() {
+                                Ok(n) if (1..=blocks.len()).contains(&n) => {
+                                    let (_, block) = &blocks[n - 1];
+                                    match save_code_block(block, Path::new(path), n).await {
+                                        Ok(path) => {
+                                            format!("Saved block {n} to {}", path.display())
+                                        }
+                                        Err(e) => format!("Couldn't save block {n}: {e}"),
+                                    }
+                                }
+                                _ => format!("There is no block {n}, see !code."),
+                            },
+                            ["all", dir] => {
+                                let mut lines = Vec::new();
+                                for (i, (_, block)) in blocks.iter().enumerate() {
+                                    let path = Path::new(dir).join(format!("block_{}", i + 1));
+                                    lines.push(match save_code_block(block, &path, i + 1).await {
+                                        Ok(path) => format!("Saved {}", path.display()),
+                                        Err(e) => format!("Couldn't save block {}: {e}", i + 1),
+                                    });
+                                }
+                                lines.join(&format!("\n{under_arrow_red} "))
+                            }
+                            _ => "Usage: !code [save N <path> | all <dir>]".to_string(),
+                        }
+                    };
+                    writer
+                        .write_all(
+                            format!("\n{system_prompt}\n{under_arrow_red} {message}\n").as_bytes(),
+                        )
+                        .await?;
+                } else if let Some(rest) = command_args(&input, "!images") {
+                    let images = last_response
+                        .as_ref()
+                        .map(|res| res.images.as_slice())
+                        .unwrap_or_default();
+                    let rest = rest.trim();
+                    let message = if images.is_empty() {
+                        "The last answer has no images.".to_string()
+                    } else if rest.is_empty() {
+                        list_images(images, &under_arrow_red)
+                    } else if let Some(dir) = rest
+                        .strip_prefix("save")
+                        .map(str::trim)
+                        .filter(|dir| !dir.is_empty())
+                    {
+                        let stem = last_response
+                            .as_ref()
+                            .map(|res| res.conversation_ids.response_id.as_str())
+                            .filter(|id| !id.is_empty())
+                            .unwrap_or("gemini_image");
+                        let mut saved = Vec::new();
+                        let mut lines = Vec::new();
+                        for (i, image) in images.iter().enumerate() {
+                            match chatbot
+                                .save_image(image, dir, &format!("{stem}_{}", i + 1))
+                                .await
+                            {
+                                Ok(path) => {
+                                    lines.push(format!("Saved {}", path.display()));
+                                    saved.push(image_markdown(
+                                        &image.alt,
+                                        &path.display().to_string(),
+                                    ));
+                                }
+                                Err(e) => lines.push(format!("Couldn't save {}: {e}", image.url)),
+                            }
+                        }
+                        if let (Some(file_path), false) = (&file_path, saved.is_empty()) {
+                            append_to_file(file_path, &format!("{}\n\n", saved.join("\n"))).await?;
+                        }
+                        lines.join(&format!("\n{under_arrow_red} "))
+                    } else {
+                        "Usage: !images [save <dir>]".to_string()
+                    };
+                    writer
+                        .write_all(
+                            format!("\n{system_prompt}\n{under_arrow_red} {message}\n").as_bytes(),
+                        )
+                        .await?;
+                } else if let Some(path) = command_args(&input, "!image") {
+                    let path = path.trim();
+                    let message = if path.is_empty() {
+                        "Usage: !image <path>".to_string()
+                    } else {
+                        match chatbot.attach_image(path).await {
+                            Ok(attachment) => format!(
+                                "Attached {}, it will be sent with your next message.",
+                                attachment.name
+                            ),
+                            Err(e) => format!("Couldn't upload {path}: {e}"),
+                        }
+                    };
+                    writer
+                        .write_all(
+                            format!("\n{system_prompt}\n{under_arrow_red} {message}\n").as_bytes(),
+                        )
+                        .await?;
+                } else if let Some(n) = command_args(&input, "!choose") {
+                    let message = match (last_response.as_mut(), n.trim().parse::<usize>()) {
+                        (Some(res), Ok(n)) if n >= 1 => {
+                            let previous = res.content.clone();
+                            if res.choose_draft(n - 1).is_some() {
+                                chatbot.set_conversation_ids(res.conversation_ids.clone());
+                                let updated = match &file_path {
+                                    Some(file_path) => {
+                                        replace_last_answer(file_path, &previous, &res.content)
+                                            .await
+                                            .map_err(|e| (file_path, e))
+                                    }
+                                    None => Ok(()),
+                                };
+                                match updated {
+                                    Ok(()) => format!("Continuing from draft {n}."),
+                                    Err((file_path, e)) => format!(
+                                        "Continuing from draft {n}, but couldn't update {}: {e}",
+                                        file_path.display()
+                                    ),
+                                }
+                            } else {
+                                format!("There is no draft {n}, see !show.")
+                            }
+                        }
+                        (None, _) => "There is no answer to choose a draft from yet.".to_string(),
+                        _ => "Usage: !choose N".to_string(),
+                    };
+                    writer
+                        .write_all(
+                            format!("\n{system_prompt}\n{under_arrow_red} {message}\n").as_bytes(),
+                        )
+                        .await?;
                 } else {
                     if let Some(file_path) = &file_path {
-                        append_to_file(file_path, &format!("**You**: {}\n\n", input)).await?;
+                        let attached: Vec<&str> = chatbot
+                            .attachments()
+                            .iter()
+                            .map(|attachment| attachment.name.as_str())
+                            .collect();
+                        let attached = if attached.is_empty() {
+                            String::new()
+                        } else {
+                            format!(" _(attached: {})_", attached.join(", "))
+                        };
+                        append_to_file(file_path, &format!("**You**: {input}{attached}\n\n"))
+                            .await?;
                     }
                     let current_time = Local::now().format("%H:%M:%S").to_string();
 
                     readline.flush()?;
-                    writer.write_all(b"\r").await?; // Clear the line before the progress bar
-
-                    let response = chatbot.ask(&input, loading_chars, &mut writer).await?;
+                    if !json {
+                        writer.write_all(b"\r").await?; // Clear the line before the progress bar
+                    }
 
-                    let response_content = response.get("content").unwrap().as_str().unwrap();
+                    let header = format!("\n\n{gemini_prompt} [{current_time}]\n{under_arrow} ");
+                    let response = match stream_answer_with_retry(
+                        &mut chatbot,
+                        &input,
+                        &loading_chars,
+                        (!json).then_some(header.as_str()),
+                        render,
+                        &retry_policy,
+                        &mut writer,
+                    )
+                    .await
+                    {
+                        Ok(response) => response,
+                        Err(e) if json => {
+                            println!("{}", args.output.render(&error_json(&e)));
+                            continue;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(
+                                    format!("\n\n{system_prompt}\n{under_arrow_red} {e}\n")
+                                        .as_bytes(),
+                                )
+                                .await?;
+                            continue;
+                        }
+                    };
 
-                    writer
-                        .write_all(format!("\n\n{gemini_prompt} [{current_time}]\n").as_bytes())
-                        .await?;
+                    let response_content = &response.content;
 
-                    if args.multi {
-                        let array = response.get("choices").unwrap().as_array().unwrap();
-
-                        for (i, object) in array.iter().enumerate() {
-                            if let Some(content_array) = object["content"].as_array() {
-                                for string in content_array {
-                                    if let Some(s) = string.as_str() {
-                                        writer
-                                            .write_all(
-                                                format!("{} {}. {}\n", under_arrow, i + 1, s)
-                                                    .as_bytes(),
-                                            )
-                                            .await?;
-                                    }
-                                }
-                            }
-                        }
-                    } else {
+                    if json {
+                        println!("{}", args.output.render(&response));
+                    } else if !response.images.is_empty() {
+                        let images = list_images(&response.images, &under_arrow);
                         writer
-                            .write_all(format!("{} {}\n", under_arrow, response_content).as_bytes())
+                            .write_all(format!("{under_arrow} {images}\n").as_bytes())
                             .await?;
                     }
+                    if !json && settings.multi {
+                        for (i, draft) in response.other_drafts().iter().enumerate() {
+                            writer
+                                .write_all(
+                                    format!(
+                                        "{} {}. {}\n",
+                                        under_arrow,
+                                        i + 2,
+                                        format_answer(&draft.text, render)
+                                    )
+                                    .as_bytes(),
+                                )
+                                .await?;
+                        }
+                    }
 
                     if let Some(file_path) = &file_path {
-                        append_to_file(file_path, &format!("**Gemini**: {}\n\n", response_content))
-                            .await?;
+                        let images: String = response
+                            .images
+                            .iter()
+                            .map(|image| format!("{}\n", image_markdown(&image.alt, &image.url)))
+                            .collect();
+                        let images = if images.is_empty() {
+                            String::new()
+                        } else {
+                            format!("{images}\n")
+                        };
+                        append_to_file(
+                            file_path,
+                            &format!("**Gemini**: {}\n\n{images}", response_content),
+                        )
+                        .await?;
                     }
 
                     last_response = Some(response);
@@ -0,0 +1,593 @@
+//! Answers formatted for the terminal: headings, emphasis, lists, aligned
+//! tables and syntax-highlighted code, wrapped to the terminal width.
+
+use std::sync::OnceLock;
+
+use colored::Colorize;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use textwrap::core::display_width;
+
+/// Width answers are wrapped to, the terminal's or 80 columns.
+pub fn terminal_width() -> usize {
+    textwrap::termwidth()
+}
+
+/// `markdown` as ANSI-styled text wrapped to `width` columns.
+///
+/// Blocks are separated by a blank line and the result ends with a newline,
+/// unless `markdown` is blank.
+pub fn render(markdown: &str, width: usize) -> String {
+    let mut renderer = Renderer::new(width.max(20));
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(markdown, options) {
+        renderer.event(event);
+    }
+    renderer.finish()
+}
+
+/// Renders an answer as it streams in, a line at a time where it can.
+///
+/// Lines are rendered once the next one has begun, unless they are in a code
+/// fence or a table, or the next one is indented and may continue them.
+/// Those are held back until their block ends or [`MarkdownStream::finish`]
+/// is called.
+#[derive(Debug)]
+pub struct MarkdownStream {
+    width: usize,
+    pending: String,
+    started: bool,
+    /// The text rendered so far ended with a blank line
+    after_block: bool,
+}
+
+impl MarkdownStream {
+    pub fn new(width: usize) -> Self {
+        Self {
+            width,
+            pending: String::new(),
+            started: false,
+            after_block: false,
+        }
+    }
+
+    /// Adds `delta` and returns the lines it finished, rendered. May be empty.
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+        let Some(end) = last_line_end(&self.pending) else {
+            return String::new();
+        };
+        let rest = self.pending.split_off(end);
+        let lines = std::mem::replace(&mut self.pending, rest);
+        self.render(&lines)
+    }
+
+    /// Renders whatever is left.
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        self.render(&rest)
+    }
+
+    /// `markdown` rendered, after a blank line if it starts a new block.
+    fn render(&mut self, markdown: &str) -> String {
+        let rendered = render(markdown, self.width);
+        if rendered.is_empty() {
+            self.after_block |= markdown.contains('\n');
+            return rendered;
+        }
+        let blank_first = is_blank(markdown.split_inclusive('\n').next());
+        let new_block = self.after_block || blank_first;
+        self.after_block = is_blank(markdown.split_inclusive('\n').next_back());
+        if std::mem::replace(&mut self.started, true) && new_block {
+            format!("\n{rendered}")
+        } else {
+            rendered
+        }
+    }
+}
+
+/// A fenced or indented code block of an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// First word of the fence info, e.g. `rust`, empty when there is none
+    pub language: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// File extension for the language, `txt` when unknown.
+    pub fn extension(&self) -> &str {
+        let language = self.language.to_ascii_lowercase();
+        let known = match language.as_str() {
+            "rust" | "rs" => "rs",
+            "python" | "py" | "python3" => "py",
+            "javascript" | "js" | "node" => "js",
+            "typescript" | "ts" => "ts",
+            "jsx" => "jsx",
+            "tsx" => "tsx",
+            "bash" | "sh" | "shell" | "zsh" | "console" => "sh",
+            "powershell" | "ps1" | "pwsh" => "ps1",
+            "c" => "c",
+            "cpp" | "c++" | "cxx" => "cpp",
+            "csharp" | "cs" | "c#" => "cs",
+            "go" | "golang" => "go",
+            "java" => "java",
+            "kotlin" | "kt" => "kt",
+            "swift" => "swift",
+            "ruby" | "rb" => "rb",
+            "php" => "php",
+            "json" => "json",
+            "yaml" | "yml" => "yaml",
+            "toml" => "toml",
+            "html" => "html",
+            "css" => "css",
+            "sql" => "sql",
+            "markdown" | "md" => "md",
+            "dockerfile" | "docker" => "dockerfile",
+            "makefile" | "make" => "mk",
+            "" | "text" | "plaintext" | "txt" => "txt",
+            _ => "",
+        };
+        if !known.is_empty() {
+            return known;
+        }
+        syntaxes()
+            .find_syntax_by_token(&self.language)
+            .and_then(|syntax| syntax.file_extensions.first())
+            .map_or("txt", String::as_str)
+    }
+}
+
+/// Code blocks of `markdown`, in order.
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    for event in Parser::new_ext(markdown, Options::empty()) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                current = Some(CodeBlock {
+                    language: fence_language(&kind),
+                    code: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(block) = &mut current {
+                    block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => blocks.extend(current.take()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// `rust` for a block fenced with ```` ```rust,ignore ````.
+fn fence_language(kind: &CodeBlockKind) -> String {
+    match kind {
+        CodeBlockKind::Fenced(info) => info.split([',', ' ', '{']).next().unwrap_or("").to_string(),
+        CodeBlockKind::Indented => String::new(),
+    }
+}
+
+fn is_blank(line: Option<&str>) -> bool {
+    line.is_some_and(|line| line.trim().is_empty())
+}
+
+/// Byte offset after the last line of `markdown` that can be rendered on its
+/// own: outside of a code fence and a table, and followed by a line that has
+/// begun and isn't indented.
+fn last_line_end(markdown: &str) -> Option<usize> {
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+    let mut end = None;
+    let mut lines = markdown.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        offset += line.len();
+        // the last line may still be growing
+        if !line.ends_with('\n') {
+            break;
+        }
+        let trimmed = line.trim_start();
+        let table_row = match fence {
+            Some(open) if trimmed.trim_end().starts_with(open) => {
+                fence = None;
+                false
+            }
+            Some(_) => continue,
+            None if trimmed.starts_with("```") => {
+                fence = Some("```");
+                continue;
+            }
+            None if trimmed.starts_with("~~~") => {
+                fence = Some("~~~");
+                continue;
+            }
+            None => trimmed.contains('|'),
+        };
+        let continued = match lines.peek() {
+            Some(next) => next.starts_with([' ', '\t']),
+            None => true,
+        };
+        if !table_row && !continued {
+            end = Some(offset);
+        }
+    }
+    end
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        themes
+            .themes
+            .remove("base16-ocean.dark")
+            .unwrap_or_default()
+    })
+}
+
+/// `code` with 24-bit colors for the syntax of `language`, plain when unknown.
+fn highlight(code: &str, language: &str) -> String {
+    let syntaxes = syntaxes();
+    let Some(syntax) = (!language.is_empty())
+        .then(|| syntaxes.find_syntax_by_token(language))
+        .flatten()
+    else {
+        return code.to_string();
+    };
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let mut out = String::new();
+    for line in LinesWithEndings::from(code) {
+        match highlighter.highlight_line(line, syntaxes) {
+            Ok(ranges) => out.push_str(&as_24_bit_terminal_escaped(&ranges, false)),
+            Err(_) => out.push_str(line),
+        }
+    }
+    out.push_str("\x1b[0m");
+    out
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+struct Renderer {
+    width: usize,
+    out: String,
+    /// Inline text of the current paragraph, heading or table cell
+    text: String,
+    bold: usize,
+    italic: usize,
+    strikethrough: usize,
+    link: usize,
+    heading: Option<HeadingLevel>,
+    quote: usize,
+    /// Next number of each open list, `None` for bullets
+    lists: Vec<Option<u64>>,
+    /// Marker of a list item that has no line yet
+    marker: Option<String>,
+    /// A list just started, so its first item is set apart from what came before
+    list_gap: bool,
+    code: Option<(String, String)>,
+    table: Option<Table>,
+    links: Vec<String>,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            ..Default::default()
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match &mut self.code {
+                Some((_, code)) => code.push_str(&text),
+                None => self.push_styled(&text),
+            },
+            Event::Code(code) => self.push(&code.yellow().to_string()),
+            Event::InlineMath(math) | Event::DisplayMath(math) => self.push(&math),
+            Event::Html(html) | Event::InlineHtml(html) => self.push(html.trim_end_matches('\n')),
+            Event::SoftBreak => self.push(" "),
+            Event::HardBreak => self.push("\n"),
+            Event::Rule => {
+                self.flush();
+                let rule = "─".repeat(self.width.saturating_sub(self.indent_width()));
+                self.block(&rule.dimmed().to_string());
+            }
+            Event::TaskListMarker(done) => self.push(if done { "[x] " } else { "[ ] " }),
+            Event::FootnoteReference(name) => self.push(&format!("[^{name}]")),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading { level, .. } => {
+                self.flush();
+                self.heading = Some(level);
+            }
+            Tag::BlockQuote(_) => {
+                self.flush();
+                self.quote += 1;
+            }
+            Tag::CodeBlock(kind) => {
+                self.flush();
+                self.code = Some((fence_language(&kind), String::new()));
+            }
+            Tag::List(start) => {
+                self.flush();
+                self.list_gap |= self.lists.is_empty();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.marker = Some(marker);
+            }
+            Tag::Table(alignments) => {
+                self.flush();
+                self.table = Some(Table {
+                    alignments,
+                    ..Default::default()
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::Emphasis => self.italic += 1,
+            Tag::Strong => self.bold += 1,
+            Tag::Strikethrough => self.strikethrough += 1,
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.link += 1;
+                self.links.push(dest_url.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => self.flush(),
+            TagEnd::Heading(_) => {
+                let text = std::mem::take(&mut self.text);
+                let text = match self.heading.take() {
+                    Some(HeadingLevel::H1) => text.bright_cyan().bold().underline(),
+                    Some(HeadingLevel::H2) => text.bright_cyan().bold(),
+                    _ => text.cyan().bold(),
+                };
+                self.wrapped(&text.to_string());
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                self.quote = self.quote.saturating_sub(1);
+            }
+            TagEnd::CodeBlock => {
+                if let Some((language, code)) = self.code.take() {
+                    self.code_block(&language, &code);
+                }
+            }
+            TagEnd::List(_) => {
+                self.flush();
+                self.lists.pop();
+            }
+            TagEnd::Item => self.flush(),
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.table_block(table);
+                }
+            }
+            TagEnd::TableCell => {
+                if let Some(table) = &mut self.table {
+                    let cell = std::mem::take(&mut self.text);
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(cell);
+                    }
+                }
+            }
+            TagEnd::Emphasis => self.italic = self.italic.saturating_sub(1),
+            TagEnd::Strong => self.bold = self.bold.saturating_sub(1),
+            TagEnd::Strikethrough => self.strikethrough = self.strikethrough.saturating_sub(1),
+            TagEnd::Link | TagEnd::Image => {
+                self.link = self.link.saturating_sub(1);
+                if let Some(url) = self.links.pop() {
+                    // `<https://...>` links already show their address
+                    if !url.is_empty() && !self.text.ends_with(&url) {
+                        self.push(&format!(" ({})", url.dimmed()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    fn push_styled(&mut self, text: &str) {
+        let mut styled = text.normal();
+        if self.bold > 0 {
+            styled = styled.bold();
+        }
+        if self.italic > 0 {
+            styled = styled.italic();
+        }
+        if self.strikethrough > 0 {
+            styled = styled.strikethrough();
+        }
+        if self.link > 0 {
+            styled = styled.underline();
+        }
+        self.text.push_str(&styled.to_string());
+    }
+
+    fn indent_width(&self) -> usize {
+        let quote = self.quote * 2;
+        let lists: usize = self
+            .lists
+            .iter()
+            .map(|list| if list.is_some() { 3 } else { 2 })
+            .sum();
+        quote + lists
+    }
+
+    /// Prefix of the lines of the current block: quote bars and list indentation.
+    fn prefixes(&mut self) -> (String, String) {
+        let quote = "│ ".dimmed().to_string().repeat(self.quote);
+        let outer: usize = self
+            .lists
+            .iter()
+            .rev()
+            .skip(1)
+            .map(|list| if list.is_some() { 3 } else { 2 })
+            .sum();
+        let outer = " ".repeat(outer);
+        match self.marker.take() {
+            Some(marker) => {
+                let hang = " ".repeat(display_width(&marker));
+                (
+                    format!("{quote}{outer}{marker}"),
+                    format!("{quote}{outer}{hang}"),
+                )
+            }
+            None if !self.lists.is_empty() => {
+                let inner = if self.lists.last().copied().flatten().is_some() {
+                    3
+                } else {
+                    2
+                };
+                let indent = format!("{quote}{outer}{}", " ".repeat(inner));
+                (indent.clone(), indent)
+            }
+            None => (quote.clone(), quote),
+        }
+    }
+
+    /// Ends the current paragraph, if any.
+    fn flush(&mut self) {
+        if self.table.is_some() || self.text.trim().is_empty() {
+            return;
+        }
+        let text = std::mem::take(&mut self.text);
+        self.wrapped(&text);
+    }
+
+    fn wrapped(&mut self, text: &str) {
+        let (first, rest) = self.prefixes();
+        // breaking only at spaces keeps URLs whole
+        let options = textwrap::Options::new(self.width)
+            .word_separator(textwrap::WordSeparator::AsciiSpace)
+            .initial_indent(&first)
+            .subsequent_indent(&rest);
+        let lines = textwrap::wrap(text.trim(), options);
+        self.separate();
+        for line in lines {
+            self.out.push_str(&line);
+            self.out.push('\n');
+        }
+    }
+
+    /// Lines that aren't wrapped, e.g. code, with the current prefixes.
+    fn block(&mut self, lines: &str) {
+        let (first, rest) = self.prefixes();
+        self.separate();
+        for (i, line) in lines.lines().enumerate() {
+            self.out.push_str(if i == 0 { &first } else { &rest });
+            self.out.push_str(line);
+            self.out.push('\n');
+        }
+    }
+
+    /// Blank line between blocks, except between the items of a tight list.
+    fn separate(&mut self) {
+        let tight = !self.lists.is_empty() && !std::mem::take(&mut self.list_gap);
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !tight {
+            self.out.push('\n');
+        }
+    }
+
+    fn code_block(&mut self, language: &str, code: &str) {
+        let label = if language.is_empty() {
+            "code"
+        } else {
+            language
+        };
+        let mut lines = format!("{}\n", format!("─── {label}").dimmed());
+        lines.push_str(&highlight(code.trim_end_matches('\n'), language));
+        self.block(&lines);
+        if !self.lists.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    fn table_block(&mut self, table: Table) {
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(display_width(cell));
+            }
+        }
+
+        let mut lines = String::new();
+        for (r, row) in table.rows.iter().enumerate() {
+            let cells: Vec<String> = (0..columns)
+                .map(|i| {
+                    let cell = row.get(i).map(String::as_str).unwrap_or("");
+                    let cell = if r == 0 {
+                        cell.bold().to_string()
+                    } else {
+                        cell.to_string()
+                    };
+                    let padding = widths[i] - display_width(&cell);
+                    let alignment = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+                    let (left, right) = match alignment {
+                        Alignment::Right => (padding, 0),
+                        Alignment::Center => (padding / 2, padding - padding / 2),
+                        _ => (0, padding),
+                    };
+                    format!("{}{cell}{}", " ".repeat(left), " ".repeat(right))
+                })
+                .collect();
+            lines.push_str(&format!("│ {} │\n", cells.join(" │ ")));
+            if r == 0 {
+                let rule: Vec<String> = widths.iter().map(|w| "─".repeat(*w)).collect();
+                lines.push_str(&format!("├─{}─┤\n", rule.join("─┼─")));
+            }
+        }
+        self.block(&lines);
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        while self.out.ends_with("\n\n") {
+            self.out.pop();
+        }
+        self.out
+    }
+}
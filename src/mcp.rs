@@ -0,0 +1,169 @@
+//! Model Context Protocol server over stdio, so other agents can consult Gemini as a tool.
+//!
+//! Messages are JSON-RPC 2.0 objects, one per line. Two tools are advertised:
+//! `gemini_ask`, continuing the conversation of an optional handle, and
+//! `gemini_reset`, forgetting a handle's conversation.
+
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::Result;
+use crate::server::Backend;
+
+/// Protocol revision answered when the client asks for one that is not in
+/// [`SUPPORTED_PROTOCOL_VERSIONS`], or for none.
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Revisions this server speaks, a client asking for one of them gets it.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Answers MCP requests from stdin on stdout until stdin is closed.
+pub async fn serve_stdio(backend: Arc<Backend>) -> Result<()> {
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    serve(backend, stdin, tokio::io::stdout()).await
+}
+
+/// Answers MCP requests read from `input` on `output` until `input` ends.
+pub async fn serve(
+    backend: Arc<Backend>,
+    input: impl AsyncBufRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle(&backend, message).await,
+            Err(e) => Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        if let Some(reply) = reply {
+            output.write_all(format!("{reply}\n").as_bytes()).await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Reply to `message`, `None` for notifications.
+async fn handle(backend: &Backend, message: Value) -> Option<Value> {
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        let id = message.get("id").cloned().unwrap_or(Value::Null);
+        return Some(error(id, INVALID_REQUEST, "missing method"));
+    };
+    // notifications, like `notifications/initialized`, get no reply
+    let id = message.get("id")?.clone();
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = match method {
+        "initialize" => {
+            let version = params
+                .get("protocolVersion")
+                .and_then(Value::as_str)
+                .filter(|version| SUPPORTED_PROTOCOL_VERSIONS.contains(version))
+                .unwrap_or(PROTOCOL_VERSION);
+            json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "bard-rs", "version": env!("CARGO_PKG_VERSION") },
+            })
+        }
+        "ping" => json!({}),
+        "tools/list" => json!({ "tools": tools() }),
+        "tools/call" => match call_tool(backend, &params).await {
+            Ok(result) => result,
+            Err(message) => return Some(error(id, INVALID_PARAMS, &message)),
+        },
+        _ => {
+            return Some(error(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unknown method {method}"),
+            ))
+        }
+    };
+    Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "gemini_ask",
+            "description": "Ask Google Gemini. Pass the same conversation handle to ask follow-up questions, leave it out to start over.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": { "type": "string", "description": "Message to send" },
+                    "conversation": {
+                        "type": "string",
+                        "description": "Any name for the conversation to continue, e.g. \"review-123\"",
+                    },
+                },
+                "required": ["prompt"],
+            },
+        },
+        {
+            "name": "gemini_reset",
+            "description": "Forget a conversation, the next gemini_ask with its handle starts a new one.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "conversation": { "type": "string", "description": "Handle to forget" },
+                },
+                "required": ["conversation"],
+            },
+        },
+    ])
+}
+
+/// Result of a `tools/call`, or why its params are unusable.
+///
+/// Gemini failing is a tool result with `isError`, so the agent sees why.
+async fn call_tool(backend: &Backend, params: &Value) -> std::result::Result<Value, String> {
+    let name = params.get("name").and_then(Value::as_str).unwrap_or("");
+    let arguments = params.get("arguments").cloned().unwrap_or(Value::Null);
+    let conversation = arguments
+        .get("conversation")
+        .and_then(Value::as_str)
+        .filter(|handle| !handle.is_empty());
+
+    match name {
+        "gemini_ask" => {
+            let prompt = arguments
+                .get("prompt")
+                .and_then(Value::as_str)
+                .filter(|prompt| !prompt.trim().is_empty())
+                .ok_or("gemini_ask needs a prompt")?;
+            Ok(match backend.ask(conversation, prompt).await {
+                Ok(response) => text_result(&response.content, false),
+                Err(e) => text_result(&e.to_string(), true),
+            })
+        }
+        "gemini_reset" => {
+            let conversation = conversation.ok_or("gemini_reset needs a conversation")?;
+            let text = if backend.forget(conversation) {
+                format!("Forgot conversation {conversation:?}")
+            } else {
+                format!("No conversation {conversation:?} to forget")
+            };
+            Ok(text_result(&text, false))
+        }
+        _ => Err(format!("unknown tool {name:?}")),
+    }
+}
+
+fn text_result(text: &str, is_error: bool) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }], "isError": is_error })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
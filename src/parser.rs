@@ -0,0 +1,136 @@
+//! Parser for the chunked (`rt=c`) body returned by the `StreamGenerate` RPC.
+//!
+//! The body starts with the `)]}'` anti-XSSI prefix, followed by frames made of
+//! a length line and a JSON array of envelopes:
+//!
+//! ```text
+//! )]}'
+//!
+//! 1234
+//! [["wrb.fr",null,"[null,[\"c_...\",\"r_...\"],...]"]]
+//! 58
+//! [["di",123],["af.httprm",122,"-123",4]]
+//! ```
+//!
+//! Only `wrb.fr` envelopes carry the answer, as a JSON document encoded in a string.
+//! The declared lengths are not trusted (they count UTF-16 units and drift whenever
+//! Google changes the framing), each frame is read as a complete JSON value instead.
+
+use serde_json::Value;
+
+use crate::error::{GeminiError, Result};
+use crate::response::GeminiResponse;
+
+const XSSI_PREFIX: &str = ")]}'";
+
+/// Splits a body into its frames. A truncated trailing frame is ignored.
+pub fn parse_frames(body: &str) -> Result<Vec<Value>> {
+    let mut frames = Vec::new();
+    let mut rest = body;
+    while let Some((frame, consumed)) = next_frame(rest)? {
+        frames.push(frame);
+        rest = &rest[consumed..];
+    }
+    Ok(frames)
+}
+
+/// Decoded payloads of every `wrb.fr` envelope, in the order they were sent.
+pub fn parse_payloads(body: &str) -> Result<Vec<Value>> {
+    Ok(parse_frames(body)?.iter().flat_map(wrb_payloads).collect())
+}
+
+/// Parses a full `StreamGenerate` body into the last complete answer it contains.
+pub fn parse_stream_generate(body: &str) -> Result<GeminiResponse> {
+    parse_payloads(body)?
+        .iter()
+        .rev()
+        .map(GeminiResponse::from_chat_data)
+        .find(GeminiResponse::is_complete)
+        .ok_or_else(|| GeminiError::unexpected_payload("no complete wrb.fr payload", body))
+}
+
+/// Reads the next frame from `input`.
+///
+/// Returns the frame and how many bytes of `input` it used, or `None` when
+/// `input` holds no complete frame yet.
+pub(crate) fn next_frame(input: &str) -> Result<Option<(Value, usize)>> {
+    let mut start = skip_whitespace(input, 0);
+    // a chunk may end inside the prefix
+    if XSSI_PREFIX.starts_with(&input[start..]) {
+        return Ok(None);
+    }
+    if input[start..].starts_with(XSSI_PREFIX) {
+        start = skip_whitespace(input, start + XSSI_PREFIX.len());
+    }
+
+    // Length line
+    let digits = input[start..]
+        .bytes()
+        .take_while(u8::is_ascii_digit)
+        .count();
+    if digits > 0 {
+        start = skip_whitespace(input, start + digits);
+    }
+    if start == input.len() {
+        return Ok(None);
+    }
+
+    let mut values = serde_json::Deserializer::from_str(&input[start..]).into_iter::<Value>();
+    match values.next() {
+        Some(Ok(frame)) => Ok(Some((frame, start + values.byte_offset()))),
+        Some(Err(e)) if e.is_eof() => Ok(None),
+        Some(Err(e)) => Err(GeminiError::unexpected_payload(
+            format!("malformed frame: {e}"),
+            &input[start..],
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Decoded payloads of the `wrb.fr` envelopes of a single frame.
+pub(crate) fn wrb_payloads(frame: &Value) -> Vec<Value> {
+    frame
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|envelope| envelope[0].as_str() == Some("wrb.fr"))
+        .filter_map(|envelope| envelope[2].as_str())
+        .filter_map(|payload| serde_json::from_str(payload).ok())
+        .collect()
+}
+
+fn skip_whitespace(input: &str, from: usize) -> usize {
+    from + input[from..].len() - input[from..].trim_start().len()
+}
+
+/// Incremental version of [`parse_frames`], fed with the body as it arrives.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `bytes` and returns every frame completed by them.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<Value>> {
+        self.buf.extend_from_slice(bytes);
+
+        // A chunk may end in the middle of a multi-byte character
+        let valid = match std::str::from_utf8(&self.buf) {
+            Ok(s) => s,
+            Err(e) => std::str::from_utf8(&self.buf[..e.valid_up_to()]).unwrap_or_default(),
+        };
+
+        let mut frames = Vec::new();
+        let mut consumed = 0;
+        while let Some((frame, len)) = next_frame(&valid[consumed..])? {
+            frames.push(frame);
+            consumed += len;
+        }
+        self.buf.drain(..consumed);
+        Ok(frames)
+    }
+}
@@ -0,0 +1,194 @@
+//! Client-side throttling, so batch jobs don't get the account flagged.
+
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// How fast prompts may be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Sustained rate, `0` disables the limiter
+    pub requests_per_minute: u32,
+    /// Requests that may be sent back to back after a quiet period
+    pub burst: u32,
+    /// Smallest gap between two requests, whatever the bucket holds
+    pub min_gap: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 30,
+            burst: 5,
+            min_gap: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn unlimited() -> Self {
+        Self {
+            requests_per_minute: 0,
+            burst: 0,
+            min_gap: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+    last_request: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst.max(1)),
+            refilled_at: Instant::now(),
+            last_request: None,
+        }
+    }
+
+    /// Takes a token if one is available, otherwise says how long to wait for it.
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let limit = self.limit;
+        if limit.requests_per_minute == 0 {
+            return Ok(());
+        }
+
+        let per_second = f64::from(limit.requests_per_minute) / 60.0;
+        let capacity = f64::from(limit.burst.max(1));
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(capacity);
+        self.refilled_at = now;
+
+        let gap_wait = self
+            .last_request
+            .map(|last| limit.min_gap.saturating_sub(now.duration_since(last)))
+            .unwrap_or_default();
+        let token_wait = if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / per_second)
+        };
+
+        let wait = gap_wait.max(token_wait);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        self.tokens -= 1.0;
+        self.last_request = Some(now);
+        Ok(())
+    }
+}
+
+/// Token bucket shared by every client it is handed to.
+///
+/// Clients use [`RateLimiter::shared`] unless given another one, so every
+/// conversation of the process draws from the same bucket.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+    /// Waiters queue here, so they are served in order
+    queue: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket::new(limit))),
+            queue: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Process-wide limiter, starting with [`RateLimit::default`].
+    pub fn shared() -> &'static RateLimiter {
+        static SHARED: OnceLock<RateLimiter> = OnceLock::new();
+        SHARED.get_or_init(|| RateLimiter::new(RateLimit::default()))
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.bucket.lock().unwrap().limit
+    }
+
+    /// Changes the limit for every client using this limiter.
+    pub fn set_limit(&self, limit: RateLimit) {
+        *self.bucket.lock().unwrap() = Bucket::new(limit);
+    }
+
+    /// Waits until a request may be sent.
+    pub async fn acquire(&self) {
+        let _turn = self.queue.lock().await;
+        loop {
+            let result = self.bucket.lock().unwrap().try_take(Instant::now());
+            match result {
+                Ok(()) => return,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(requests_per_minute: u32, burst: u32, min_gap_ms: u64) -> (Bucket, Instant) {
+        let bucket = Bucket::new(RateLimit {
+            requests_per_minute,
+            burst,
+            min_gap: Duration::from_millis(min_gap_ms),
+        });
+        let start = bucket.refilled_at;
+        (bucket, start)
+    }
+
+    #[test]
+    fn burst_is_spent_then_waits_for_a_token() {
+        let (mut bucket, now) = bucket(60, 3, 0);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.try_take(now), Ok(()));
+        }
+        assert_eq!(bucket.try_take(now), Err(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn min_gap_holds_even_with_tokens_left() {
+        let (mut bucket, now) = bucket(60, 5, 500);
+
+        assert_eq!(bucket.try_take(now), Ok(()));
+        let later = now + Duration::from_millis(200);
+        assert_eq!(bucket.try_take(later), Err(Duration::from_millis(300)));
+        assert_eq!(bucket.try_take(now + Duration::from_millis(500)), Ok(()));
+    }
+
+    #[test]
+    fn tokens_refill_at_the_sustained_rate() {
+        let (mut bucket, now) = bucket(30, 1, 0);
+
+        assert_eq!(bucket.try_take(now), Ok(()));
+        // one token every 2 seconds
+        let later = now + Duration::from_secs(1);
+        assert_eq!(bucket.try_take(later), Err(Duration::from_secs(1)));
+        assert_eq!(bucket.try_take(now + Duration::from_secs(2)), Ok(()));
+        // a long pause refills up to the burst only
+        let idle = now + Duration::from_secs(600);
+        assert_eq!(bucket.try_take(idle), Ok(()));
+        assert!(bucket.try_take(idle).is_err());
+    }
+
+    #[test]
+    fn zero_requests_per_minute_is_unlimited() {
+        let (mut bucket, now) = bucket(0, 0, 500);
+
+        for _ in 0..100 {
+            assert_eq!(bucket.try_take(now), Ok(()));
+        }
+    }
+}
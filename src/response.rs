@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Ids the web app needs to continue a conversation from a specific answer.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationIds {
+    pub conversation_id: String,
+    pub response_id: String,
+    pub choice_id: String,
+}
+
+impl ConversationIds {
+    /// `true` when there is no conversation to continue yet.
+    pub fn is_empty(&self) -> bool {
+        self.conversation_id.is_empty() && self.response_id.is_empty() && self.choice_id.is_empty()
+    }
+}
+
+/// One of the candidate answers Gemini generated for a prompt.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Draft {
+    /// Choice id, starts with `rc_`
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub images: Vec<Image>,
+}
+
+/// Where an [`Image`] comes from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageKind {
+    /// Found on the web
+    #[default]
+    Web,
+    /// Drawn by Gemini
+    Generated,
+}
+
+/// An image shown with an answer.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image {
+    pub url: String,
+    /// Alt text, may be empty
+    pub alt: String,
+    pub kind: ImageKind,
+    /// Page a web image was found on
+    pub source: Option<String>,
+}
+
+impl Image {
+    /// Web images of the legacy `choice[4]` and the current `choice[12][1]`,
+    /// then the images Gemini generated (`choice[12][7][0]`).
+    fn parse_all(choice: &Value) -> Vec<Self> {
+        let web = [&choice[4], &choice[12][1]]
+            .into_iter()
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter_map(|image| {
+                Some(Self {
+                    url: image[0][0][0].as_str()?.to_string(),
+                    alt: first_str(&[&image[0][4], &image[2], &image[7][0]]),
+                    kind: ImageKind::Web,
+                    source: image[1][0][0].as_str().map(str::to_string),
+                })
+            });
+        let generated = choice[12][7][0]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|image| {
+                Some(Self {
+                    url: image[0][3][3].as_str()?.to_string(),
+                    alt: first_str(&[&image[3][5][0], &image[0][3][2]]),
+                    kind: ImageKind::Generated,
+                    source: None,
+                })
+            });
+        web.chain(generated)
+            .filter(|image| image.url.starts_with("http"))
+            .collect()
+    }
+}
+
+/// First non-empty string among `values`.
+fn first_str(values: &[&Value]) -> String {
+    values
+        .iter()
+        .filter_map(|value| value.as_str())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Location Gemini used to answer the prompt.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub address: String,
+    pub place_type: Option<String>,
+}
+
+/// Parsed answer of a single `ask`.
+///
+/// The serialized field names are the `--output json` schema of the CLI, keep them stable.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeminiResponse {
+    /// Text of the draft the conversation continues from, the one Gemini shows
+    /// by default until [`choose_draft`](Self::choose_draft) picks another
+    pub content: String,
+    /// Images of the draft the conversation continues from
+    #[serde(default)]
+    pub images: Vec<Image>,
+    /// Every draft, starting with the default one
+    pub drafts: Vec<Draft>,
+    pub conversation_ids: ConversationIds,
+    /// Query Gemini rewrote the prompt into
+    pub text_query: String,
+    pub location: Option<Location>,
+}
+
+impl GeminiResponse {
+    /// Drafts other than the default answer.
+    pub fn other_drafts(&self) -> &[Draft] {
+        self.drafts.get(1..).unwrap_or_default()
+    }
+
+    /// Makes draft `index` (0-based) the answer the conversation continues from,
+    /// like picking one of the "other drafts" in the web app.
+    pub fn choose_draft(&mut self, index: usize) -> Option<&Draft> {
+        let draft = self
+            .drafts
+            .get(index)
+            .filter(|draft| !draft.id.is_empty())?;
+        self.content = draft.text.clone();
+        self.images = draft.images.clone();
+        self.conversation_ids.choice_id = draft.id.clone();
+        Some(draft)
+    }
+
+    /// `true` when the payload had an answer and every id needed to continue from it.
+    pub fn is_complete(&self) -> bool {
+        let ids = &self.conversation_ids;
+        !self.drafts.is_empty()
+            && !ids.conversation_id.is_empty()
+            && !ids.response_id.is_empty()
+            && !ids.choice_id.is_empty()
+    }
+
+    /// Builds a response out of the inner (already decoded) chat payload.
+    pub(crate) fn from_chat_data(json_chat_data: &Value) -> Self {
+        let drafts: Vec<Draft> = json_chat_data[4]
+            .as_array()
+            .map(|choices| {
+                choices
+                    .iter()
+                    .map(|choice| Draft {
+                        id: choice[0].as_str().unwrap_or_default().to_string(),
+                        text: choice[1][0].as_str().unwrap_or_default().to_string(),
+                        images: Image::parse_all(choice),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // sometimes, there is no draft id.
+        // If not found, search for an element that starts with "rc_"
+        let choice_id = drafts
+            .first()
+            .map(|draft| draft.id.clone())
+            .filter(|id| !id.is_empty())
+            .or_else(|| find_choice_id(json_chat_data))
+            .unwrap_or_default();
+
+        // factualityQueries is now null, so it is not parsed
+        let location = json_chat_data[7][0].as_str().map(|address| Location {
+            address: address.to_string(),
+            place_type: json_chat_data[7][1].as_str().map(str::to_string),
+        });
+
+        Self {
+            content: drafts
+                .first()
+                .map(|draft| draft.text.clone())
+                .unwrap_or_default(),
+            images: drafts
+                .first()
+                .map(|draft| draft.images.clone())
+                .unwrap_or_default(),
+            conversation_ids: ConversationIds {
+                conversation_id: json_chat_data[1][0]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                response_id: json_chat_data[1][1]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                choice_id,
+            },
+            text_query: json_chat_data[2][0][0]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            location,
+            drafts,
+        }
+    }
+}
+
+fn find_choice_id(json_chat_data: &Value) -> Option<String> {
+    let items = json_chat_data.as_array()?;
+    for item in items.iter().filter_map(Value::as_array) {
+        for sub_item in item {
+            let candidates = match sub_item {
+                Value::Array(inner) => inner.iter().collect::<Vec<_>>(),
+                other => vec![other],
+            };
+            if let Some(s) = candidates
+                .into_iter()
+                .filter_map(Value::as_str)
+                .find(|s| s.starts_with("rc_"))
+            {
+                return Some(s.to_string());
+            }
+        }
+    }
+    None
+}
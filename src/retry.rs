@@ -0,0 +1,65 @@
+//! When and how long to wait before sending a rejected prompt again.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::error::GeminiError;
+
+/// Exponential backoff with jitter, honoring `Retry-After`.
+///
+/// Only errors where Google refused the request up front are retried
+/// (see [`GeminiError::is_retryable`]): a prompt is never sent twice because
+/// an answer broke halfway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, `0` disables retrying
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub base_delay: Duration,
+    /// Upper bound of the backoff, also used for CAPTCHA blocks
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait before retry number `retry` (starting at 0) after `error`,
+    /// or `None` when the error should be returned as is.
+    pub fn delay_for(&self, retry: u32, error: &GeminiError) -> Option<Duration> {
+        if retry >= self.max_retries || !error.is_retryable() {
+            return None;
+        }
+
+        // A CAPTCHA block only expires once the requests stop for a while
+        let backoff = match error {
+            GeminiError::Captcha => self.max_delay,
+            _ => self
+                .base_delay
+                .saturating_mul(2u32.saturating_pow(retry))
+                .min(self.max_delay),
+        };
+        let jittered = backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+
+        Some(match error.retry_after() {
+            Some(retry_after) => retry_after.max(jittered),
+            None => jittered,
+        })
+    }
+}
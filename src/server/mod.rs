@@ -0,0 +1,337 @@
+//! HTTP APIs other tools already speak, answered through the Gemini web session.
+//!
+//! Every request is either a new conversation or, when the client sends a
+//! conversation key, the continuation of the one that key started.
+
+use std::collections::HashMap;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use futures_util::StreamExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use crate::client::GeminiClient;
+use crate::error::{GeminiError, Result};
+use crate::response::{ConversationIds, GeminiResponse};
+use crate::stream::StreamEvent;
+
+pub mod ollama;
+pub mod openai;
+
+/// Model name the APIs advertise. Any name is accepted in requests.
+pub const MODEL: &str = "gemini";
+
+/// Header carrying the conversation key, for clients that can't set `user`.
+pub const CONVERSATION_HEADER: &str = "x-conversation-key";
+
+/// The Gemini session shared by every request.
+///
+/// Prompts are sent one at a time, so a conversation is never continued from
+/// two places at once.
+pub struct Backend {
+    client: tokio::sync::Mutex<GeminiClient>,
+    conversations: Mutex<HashMap<String, ConversationIds>>,
+}
+
+impl Backend {
+    pub fn new(client: GeminiClient) -> Self {
+        Self {
+            client: tokio::sync::Mutex::new(client),
+            conversations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forgets the conversation of `key`, `false` if there was none.
+    pub fn forget(&self, key: &str) -> bool {
+        self.conversations.lock().unwrap().remove(key).is_some()
+    }
+
+    /// Sends `prompt` in the conversation of `key`, or in a new one.
+    pub async fn ask(&self, key: Option<&str>, prompt: &str) -> Result<GeminiResponse> {
+        self.ask_with(key, |_| Ok(prompt.to_string())).await
+    }
+
+    /// [`Backend::ask`] with the prompt [`build_prompt`] makes of `messages`.
+    pub(crate) async fn ask_messages(
+        &self,
+        key: Option<&str>,
+        messages: &[Message],
+    ) -> Result<GeminiResponse> {
+        self.ask_with(key, |continuing| prompt_for(messages, continuing))
+            .await
+    }
+
+    /// Whether the conversation is continued is only known once the client
+    /// is locked, so `prompt` is given it then.
+    async fn ask_with(
+        &self,
+        key: Option<&str>,
+        prompt: impl FnOnce(bool) -> Result<String>,
+    ) -> Result<GeminiResponse> {
+        let mut client = self.client.lock().await;
+        let prompt = prompt(self.resume(&mut client, key))?;
+        let response = client.ask(&prompt).await?;
+        self.remember(&client, key);
+        Ok(response)
+    }
+
+    /// [`Backend::ask_messages`], handing out the answer as it is generated.
+    ///
+    /// The prompt is sent from a task of its own, so the answer keeps coming
+    /// while the receiver is moved into a response body.
+    pub(crate) fn ask_messages_stream(
+        self: &Arc<Self>,
+        key: Option<String>,
+        messages: Vec<Message>,
+    ) -> mpsc::Receiver<Result<StreamEvent>> {
+        let (tx, rx) = mpsc::channel(16);
+        let backend = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut client = backend.client.lock().await;
+            let continuing = backend.resume(&mut client, key.as_deref());
+            let prompt = match prompt_for(&messages, continuing) {
+                Ok(prompt) => prompt,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+
+            {
+                let stream = match client.ask_stream(&prompt).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                };
+                let mut stream = pin!(stream);
+                while let Some(event) = stream.next().await {
+                    let last = !matches!(event, Ok(StreamEvent::Delta(_)));
+                    // stop generating for a client that went away
+                    if tx.send(event).await.is_err() || last {
+                        break;
+                    }
+                }
+            }
+            backend.remember(&client, key.as_deref());
+        });
+        rx
+    }
+
+    /// Points `client` at the conversation of `key`, `true` if Gemini remembers one.
+    fn resume(&self, client: &mut GeminiClient, key: Option<&str>) -> bool {
+        let ids = key.and_then(|key| self.conversations.lock().unwrap().get(key).cloned());
+        let continuing = ids.is_some();
+        client.set_conversation_ids(ids.unwrap_or_default());
+        continuing
+    }
+
+    fn remember(&self, client: &GeminiClient, key: Option<&str>) {
+        let ids = client.conversation_ids();
+        if let Some(key) = key.filter(|_| !ids.is_empty()) {
+            self.conversations
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), ids.clone());
+        }
+    }
+}
+
+/// A chat message of one of the APIs, reduced to what Gemini can use.
+pub(crate) struct Message {
+    pub(crate) role: String,
+    pub(crate) text: String,
+}
+
+/// What to send Gemini for `messages`.
+///
+/// A conversation Gemini remembers only needs the messages after the last
+/// answer. Otherwise the whole history is written out as a transcript, unless
+/// it is a single user message. `None` when there is nothing to send.
+pub(crate) fn build_prompt(messages: &[Message], continuing: bool) -> Option<String> {
+    let messages = if continuing {
+        let start = messages
+            .iter()
+            .rposition(|m| m.role == "assistant")
+            .map_or(0, |i| i + 1);
+        &messages[start..]
+    } else {
+        messages
+    };
+    let messages: Vec<&Message> = messages
+        .iter()
+        .filter(|m| !m.text.trim().is_empty())
+        .collect();
+
+    if messages.is_empty() {
+        return None;
+    }
+    let plain = continuing || matches!(messages[..], [only] if only.role == "user");
+    let parts: Vec<String> = messages
+        .iter()
+        .map(|m| {
+            if plain {
+                m.text.clone()
+            } else {
+                format!("{}: {}", role_label(&m.role), m.text)
+            }
+        })
+        .collect();
+    Some(parts.join("\n\n"))
+}
+
+/// `false` when none of `messages` has any text, so there is nothing to send
+/// whatever the conversation.
+pub(crate) fn has_text(messages: &[Message]) -> bool {
+    messages.iter().any(|m| !m.text.trim().is_empty())
+}
+
+fn prompt_for(messages: &[Message], continuing: bool) -> Result<String> {
+    build_prompt(messages, continuing).ok_or_else(|| {
+        GeminiError::InvalidConfig("no message after the last answer to send".to_string())
+    })
+}
+
+fn role_label(role: &str) -> &str {
+    match role {
+        "system" | "developer" => "System",
+        "assistant" => "Assistant",
+        "user" => "User",
+        other => other,
+    }
+}
+
+/// HTTP status a failed prompt is reported with.
+pub(crate) fn status_for(err: &GeminiError) -> StatusCode {
+    match err {
+        GeminiError::RateLimited { .. } | GeminiError::Captcha => StatusCode::TOO_MANY_REQUESTS,
+        GeminiError::InvalidConfig(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// Conversation key of a request: the `user` field if any, else [`CONVERSATION_HEADER`].
+pub(crate) fn conversation_key(
+    headers: &axum::http::HeaderMap,
+    user: Option<&str>,
+) -> Option<String> {
+    user.filter(|user| !user.is_empty())
+        .map(str::to_string)
+        .or_else(|| {
+            headers
+                .get(CONVERSATION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        })
+}
+
+#[derive(Clone)]
+struct AppState {
+    api_key: Option<Arc<str>>,
+}
+
+/// Serves the APIs on `listener` until the process is stopped.
+///
+/// With an `api_key`, requests must send it as `Authorization: Bearer <key>`.
+pub async fn serve(
+    backend: Arc<Backend>,
+    listener: TcpListener,
+    api_key: Option<String>,
+) -> Result<()> {
+    let state = AppState {
+        api_key: api_key.filter(|key| !key.is_empty()).map(Arc::from),
+    };
+    let app = Router::new()
+        .merge(openai::routes(Arc::clone(&backend)))
+        .merge(ollama::routes(backend))
+        .layer(middleware::from_fn_with_state(state, authorize));
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn authorize(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if let Some(api_key) = &state.api_key {
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer != Some(api_key) {
+            let body = serde_json::json!({
+                "error": { "message": "invalid API key", "type": "invalid_request_error" }
+            });
+            return (StatusCode::UNAUTHORIZED, axum::Json(body)).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(list: &[(&str, &str)]) -> Vec<Message> {
+        list.iter()
+            .map(|(role, text)| Message {
+                role: role.to_string(),
+                text: text.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_user_message_is_sent_as_is() {
+        let messages = messages(&[("user", "Hi"), ("assistant", "  ")]);
+
+        assert_eq!(build_prompt(&messages, false).as_deref(), Some("Hi"));
+    }
+
+    #[test]
+    fn new_conversation_gets_the_whole_transcript() {
+        let messages = messages(&[
+            ("developer", "Be brief"),
+            ("user", "Hi"),
+            ("assistant", "Hello"),
+            ("tool", "42"),
+            ("user", "Thanks"),
+        ]);
+
+        assert_eq!(
+            build_prompt(&messages, false).as_deref(),
+            Some("System: Be brief\n\nUser: Hi\n\nAssistant: Hello\n\ntool: 42\n\nUser: Thanks")
+        );
+    }
+
+    #[test]
+    fn continued_conversation_only_gets_the_messages_after_the_last_answer() {
+        let messages = messages(&[
+            ("system", "Be brief"),
+            ("user", "Hi"),
+            ("assistant", "Hello"),
+            ("system", "Answer in French"),
+            ("user", "Thanks"),
+        ]);
+
+        assert_eq!(
+            build_prompt(&messages, true).as_deref(),
+            Some("Answer in French\n\nThanks")
+        );
+    }
+
+    #[test]
+    fn nothing_to_send() {
+        assert_eq!(build_prompt(&[], false), None);
+        assert_eq!(build_prompt(&messages(&[("user", " \n")]), false), None);
+        let answered = messages(&[("user", "Hi"), ("assistant", "Hello")]);
+        assert_eq!(build_prompt(&answered, true), None);
+        assert!(build_prompt(&answered, false).is_some());
+    }
+}
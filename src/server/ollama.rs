@@ -0,0 +1,237 @@
+//! `/api/chat`, `/api/generate` and `/api/tags` of the Ollama API.
+//!
+//! Ollama has no field for a conversation key, send the `X-Conversation-Key`
+//! header to keep talking in the same Gemini conversation.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{conversation_key, has_text, status_for, Backend, Message, MODEL};
+use crate::error::GeminiError;
+use crate::stream::{unstreamed, StreamEvent};
+
+pub(crate) fn routes(backend: Arc<Backend>) -> Router {
+    Router::new()
+        .route("/", get(|| async { "Ollama is running" }))
+        .route("/api/tags", get(tags))
+        .route("/api/chat", post(chat))
+        .route("/api/generate", post(generate))
+        .with_state(backend)
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatRequest {
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateRequest {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    prompt: String,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default)]
+    stream: Option<bool>,
+}
+
+/// Which endpoint an answer is for, they name the text differently.
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Chat,
+    Generate,
+}
+
+async fn tags() -> Json<Value> {
+    let name = format!("{MODEL}:latest");
+    Json(json!({
+        "models": [{
+            "name": name,
+            "model": name,
+            "modified_at": "1970-01-01T00:00:00Z",
+            "size": 0,
+            "digest": "",
+            "details": {
+                "format": "",
+                "family": MODEL,
+                "families": [MODEL],
+                "parameter_size": "",
+                "quantization_level": "",
+            },
+        }],
+    }))
+}
+
+async fn chat(State(backend): State<Arc<Backend>>, headers: HeaderMap, body: Bytes) -> Response {
+    // Ollama clients often leave out `Content-Type`, so the body is read as JSON whatever it says
+    let request: ChatRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let key = conversation_key(&headers, None);
+    let messages: Vec<Message> = request
+        .messages
+        .into_iter()
+        .map(|m| Message {
+            role: m.role,
+            text: m.content,
+        })
+        .collect();
+    if !has_text(&messages) {
+        return error_response(StatusCode::BAD_REQUEST, "messages are empty");
+    }
+
+    // Ollama streams unless told otherwise
+    let stream = request.stream.unwrap_or(true);
+    answer(
+        &backend,
+        key,
+        messages,
+        request.model,
+        stream,
+        Endpoint::Chat,
+    )
+    .await
+}
+
+async fn generate(
+    State(backend): State<Arc<Backend>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request: GenerateRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let key = conversation_key(&headers, None);
+    let mut messages = Vec::new();
+    if let Some(system) = request.system {
+        messages.push(Message {
+            role: "system".to_string(),
+            text: system,
+        });
+    }
+    messages.push(Message {
+        role: "user".to_string(),
+        text: request.prompt,
+    });
+    if !has_text(&messages) {
+        return error_response(StatusCode::BAD_REQUEST, "prompt is empty");
+    }
+
+    let stream = request.stream.unwrap_or(true);
+    answer(
+        &backend,
+        key,
+        messages,
+        request.model,
+        stream,
+        Endpoint::Generate,
+    )
+    .await
+}
+
+async fn answer(
+    backend: &Arc<Backend>,
+    key: Option<String>,
+    messages: Vec<Message>,
+    model: Option<String>,
+    stream: bool,
+    endpoint: Endpoint,
+) -> Response {
+    let model = model.unwrap_or_else(|| format!("{MODEL}:latest"));
+    if stream {
+        return stream_answer(backend, key, messages, model, endpoint);
+    }
+
+    match backend.ask_messages(key.as_deref(), &messages).await {
+        Ok(response) => Json(line(&model, endpoint, &response.content, true)).into_response(),
+        Err(e) => gemini_error_response(&e),
+    }
+}
+
+/// One JSON object per line, the last one with `"done": true`.
+fn stream_answer(
+    backend: &Arc<Backend>,
+    key: Option<String>,
+    messages: Vec<Message>,
+    model: String,
+    endpoint: Endpoint,
+) -> Response {
+    let events = backend.ask_messages_stream(key, messages);
+    let lines = stream::unfold(Some((events, String::new())), move |state| {
+        let model = model.clone();
+        async move {
+            let (mut events, mut streamed) = state?;
+            let line = match events.recv().await? {
+                Ok(StreamEvent::Delta(delta)) => {
+                    let line = line(&model, endpoint, &delta, false);
+                    streamed.push_str(&delta);
+                    return Some((line, Some((events, streamed))));
+                }
+                // the deltas may not cover the final version of the answer
+                Ok(StreamEvent::Done(response)) => line(
+                    &model,
+                    endpoint,
+                    unstreamed(&streamed, &response.content),
+                    true,
+                ),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            Some((line, None))
+        }
+    })
+    .map(|line| Ok::<_, Infallible>(format!("{line}\n")));
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+fn line(model: &str, endpoint: Endpoint, text: &str, done: bool) -> Value {
+    let mut line = json!({
+        "model": model,
+        "created_at": chrono::Utc::now().to_rfc3339(),
+        "done": done,
+    });
+    match endpoint {
+        Endpoint::Chat => line["message"] = json!({ "role": "assistant", "content": text }),
+        Endpoint::Generate => line["response"] = json!(text),
+    }
+    if done {
+        line["done_reason"] = json!("stop");
+    }
+    line
+}
+
+fn gemini_error_response(err: &GeminiError) -> Response {
+    error_response(status_for(err), &err.to_string())
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
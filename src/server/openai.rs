@@ -0,0 +1,213 @@
+//! `/v1/chat/completions` and `/v1/models` of the OpenAI API.
+//!
+//! Set `user` (or the `X-Conversation-Key` header) to keep talking in the same
+//! Gemini conversation, only the new messages are sent then. Without it, every
+//! request is a new conversation. `n` picks up to that many of Gemini's drafts.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{conversation_key, has_text, status_for, Backend, Message, MODEL};
+use crate::error::GeminiError;
+use crate::stream::{unstreamed, StreamEvent};
+
+pub(crate) fn routes(backend: Arc<Backend>) -> Router {
+    Router::new()
+        .route("/v1/models", get(models))
+        .route("/v1/chat/completions", post(chat_completions))
+        .with_state(backend)
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    n: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    /// A string, or an array of `{"type": "text", "text": ...}` parts
+    #[serde(default)]
+    content: Value,
+}
+
+impl ChatMessage {
+    fn text(&self) -> String {
+        match &self.content {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part.get("text")?.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        }
+    }
+}
+
+async fn models() -> Json<Value> {
+    Json(json!({
+        "object": "list",
+        "data": [{ "id": MODEL, "object": "model", "created": 0, "owned_by": "google" }],
+    }))
+}
+
+async fn chat_completions(
+    State(backend): State<Arc<Backend>>,
+    headers: HeaderMap,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Response {
+    let key = conversation_key(&headers, request.user.as_deref());
+    let messages: Vec<Message> = request
+        .messages
+        .iter()
+        .map(|m| Message {
+            role: m.role.clone(),
+            text: m.text(),
+        })
+        .collect();
+    if !has_text(&messages) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "messages don't contain any text to send",
+        );
+    }
+    let model = request.model.unwrap_or_else(|| MODEL.to_string());
+    let created = chrono::Utc::now().timestamp();
+
+    if request.stream {
+        return stream_completion(&backend, key, messages, model, created);
+    }
+
+    let response = match backend.ask_messages(key.as_deref(), &messages).await {
+        Ok(response) => response,
+        Err(e) => return gemini_error_response(&e),
+    };
+    let choices: Vec<Value> = response
+        .drafts
+        .iter()
+        .map(|draft| draft.text.as_str())
+        .chain(
+            response
+                .drafts
+                .is_empty()
+                .then_some(response.content.as_str()),
+        )
+        .take(request.n.unwrap_or(1).max(1))
+        .enumerate()
+        .map(|(index, text)| {
+            json!({
+                "index": index,
+                "message": { "role": "assistant", "content": text },
+                "finish_reason": "stop",
+            })
+        })
+        .collect();
+
+    Json(json!({
+        "id": completion_id(&response.conversation_ids.response_id),
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": choices,
+        "usage": { "prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0 },
+    }))
+    .into_response()
+}
+
+/// Server-sent `chat.completion.chunk`s, ending with `data: [DONE]`.
+fn stream_completion(
+    backend: &Arc<Backend>,
+    key: Option<String>,
+    messages: Vec<Message>,
+    model: String,
+    created: i64,
+) -> Response {
+    let events = backend.ask_messages_stream(key, messages);
+    let id = completion_id("");
+    let chunk = move |delta: Value, finish_reason: Option<&str>| {
+        let chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        });
+        Event::default().data(chunk.to_string())
+    };
+
+    let first = chunk(json!({ "role": "assistant", "content": "" }), None);
+    let answer = stream::unfold(Some((events, String::new())), move |state| {
+        let chunk = chunk.clone();
+        async move {
+            let (mut events, mut streamed) = state?;
+            let out = match events.recv().await? {
+                Ok(StreamEvent::Delta(delta)) => {
+                    streamed.push_str(&delta);
+                    let event = chunk(json!({ "content": delta }), None);
+                    return Some((vec![event], Some((events, streamed))));
+                }
+                Ok(StreamEvent::Done(response)) => {
+                    // the deltas may not cover the final version of the answer
+                    let rest = unstreamed(&streamed, &response.content);
+                    let mut out = Vec::new();
+                    if !rest.is_empty() {
+                        out.push(chunk(json!({ "content": rest }), None));
+                    }
+                    out.push(chunk(json!({}), Some("stop")));
+                    out
+                }
+                Err(e) => vec![Event::default().data(error_body(&e).to_string())],
+            };
+            Some((out, None))
+        }
+    });
+    let body = stream::once(async { first })
+        .chain(answer.flat_map(stream::iter))
+        .chain(stream::once(async { Event::default().data("[DONE]") }))
+        .map(Ok::<_, Infallible>);
+
+    Sse::new(body).into_response()
+}
+
+fn completion_id(response_id: &str) -> String {
+    let id = response_id.trim_start_matches("r_");
+    if id.is_empty() {
+        format!("chatcmpl-{:x}", rand::random::<u64>())
+    } else {
+        format!("chatcmpl-{id}")
+    }
+}
+
+fn error_body(err: &GeminiError) -> Value {
+    json!({ "error": { "message": err.to_string(), "type": err.kind(), "code": null } })
+}
+
+fn gemini_error_response(err: &GeminiError) -> Response {
+    (status_for(err), Json(error_body(err))).into_response()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let body = json!({
+        "error": { "message": message, "type": "invalid_request_error", "code": null }
+    });
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,62 @@
+//! Conversations saved to disk so they can be resumed in a later run.
+
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{GeminiError, Result};
+use crate::response::{ConversationIds, GeminiResponse};
+
+/// Everything needed to continue a conversation later.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub conversation_ids: ConversationIds,
+    /// Markdown transcript the conversation is being saved to
+    pub transcript_path: Option<PathBuf>,
+    pub last_response: Option<GeminiResponse>,
+    /// RFC 3339 timestamp of the last save
+    pub saved_at: String,
+}
+
+impl Session {
+    /// Directory sessions are stored in, e.g. `~/.local/share/bard-rs/sessions` on Linux.
+    pub fn dir() -> Result<PathBuf> {
+        dirs::data_local_dir()
+            .map(|dir| dir.join("bard-rs").join("sessions"))
+            .ok_or_else(|| GeminiError::InvalidConfig("no data directory found".to_string()))
+    }
+
+    /// State file of the session called `name`.
+    pub fn path(name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(GeminiError::InvalidConfig(format!(
+                "session name {name:?} may only contain letters, digits, '-' and '_'"
+            )));
+        }
+        Ok(Self::dir()?.join(format!("{name}.json")))
+    }
+
+    /// Writes the session to its state file, replacing any previous save.
+    pub fn save(&mut self, name: &str) -> Result<PathBuf> {
+        let path = Self::path(name)?;
+        self.saved_at = chrono::Local::now().to_rfc3339();
+
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::create_dir_all(Self::dir()?)?;
+        std::fs::write(&path, json)?;
+        Ok(path)
+    }
+
+    /// Reads the session saved as `name`.
+    pub fn load(name: &str) -> Result<Self> {
+        let json = std::fs::read_to_string(Self::path(name)?)?;
+        Ok(serde_json::from_str(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+
+use futures_util::{stream, Stream};
+
+use crate::client::GeminiClient;
+use crate::error::{GeminiError, Result};
+use crate::parser::{self, FrameDecoder};
+use crate::response::GeminiResponse;
+
+/// Shortest end of the text handed out that [`unstreamed`] looks for in a
+/// rewritten answer.
+const MIN_ANCHOR: usize = 8;
+/// Longest one, so the search stays cheap on long answers.
+const MAX_ANCHOR: usize = 64;
+
+/// Item of [`GeminiClient::ask_stream`].
+///
+/// Gemini sometimes rewrites text it already sent, e.g. to add citations or
+/// replace image placeholders. The deltas then carry on from where the earlier
+/// version ended, so joined together they can differ from the content of
+/// `Done`, which is the final version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// Text appended to the default answer since the previous delta.
+    Delta(String),
+    /// The whole answer, always the last event.
+    Done(GeminiResponse),
+}
+
+/// Part of `content` that follows the text a reader already has, `streamed`.
+///
+/// That is the tail of `content` when it extends `streamed`. When earlier
+/// text was rewritten, it is what follows the end of `streamed` in `content`,
+/// or everything after the text both share when that end can't be found.
+pub fn unstreamed<'a>(streamed: &str, content: &'a str) -> &'a str {
+    if let Some(tail) = content.strip_prefix(streamed) {
+        return tail;
+    }
+
+    let common = streamed
+        .char_indices()
+        .zip(content.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(streamed.len().min(content.len()), |((i, _), _)| i);
+    let rewritten = &content[common..];
+
+    // the longest end of `streamed` still in the rewritten text marks where it stopped
+    let ends: Vec<usize> = streamed.char_indices().map(|(i, _)| i).rev().collect();
+    let longest = ends.len().min(MAX_ANCHOR);
+    let shortest = ends.len().min(MIN_ANCHOR);
+    for len in (shortest..=longest).rev() {
+        let anchor = &streamed[ends[len - 1]..];
+        if let Some(i) = rewritten.find(anchor) {
+            return &rewritten[i + anchor.len()..];
+        }
+    }
+    rewritten
+}
+
+struct AnswerState<'a> {
+    client: &'a mut GeminiClient,
+    resp: Option<reqwest::Response>,
+    decoder: FrameDecoder,
+    /// Latest version of the answer the deltas were taken from
+    sent: String,
+    latest: Option<GeminiResponse>,
+    pending: VecDeque<StreamEvent>,
+}
+
+impl AnswerState<'_> {
+    fn handle_frames(&mut self, frames: Vec<serde_json::Value>) {
+        for payload in frames.iter().flat_map(parser::wrb_payloads) {
+            let response = GeminiResponse::from_chat_data(&payload);
+
+            // Each payload repeats the answer so far, only hand out what's new.
+            // Empty or stale payloads have nothing new.
+            let content = &response.content;
+            if !content.is_empty() && !self.sent.starts_with(content.as_str()) {
+                let delta = unstreamed(&self.sent, content);
+                if !delta.is_empty() {
+                    self.pending
+                        .push_back(StreamEvent::Delta(delta.to_string()));
+                }
+                self.sent = content.clone();
+            }
+
+            if response.is_complete() {
+                self.latest = Some(response);
+            }
+        }
+    }
+
+    /// Called once the body is over, yields `Done` or why there is no answer.
+    fn finish(&mut self) -> Result<StreamEvent> {
+        match self.latest.take() {
+            Some(response) => {
+                self.client.finish_turn(&response);
+                Ok(StreamEvent::Done(response))
+            }
+            None => Err(GeminiError::unexpected_payload(
+                "no complete wrb.fr payload",
+                &self.sent,
+            )),
+        }
+    }
+}
+
+pub(crate) fn answer_stream(
+    client: &mut GeminiClient,
+    resp: reqwest::Response,
+) -> impl Stream<Item = Result<StreamEvent>> + '_ {
+    let state = AnswerState {
+        client,
+        resp: Some(resp),
+        decoder: FrameDecoder::new(),
+        sent: String::new(),
+        latest: None,
+        pending: VecDeque::new(),
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+
+            let resp = state.resp.as_mut()?;
+            let item = match resp.chunk().await {
+                Ok(Some(bytes)) => match state.decoder.push(&bytes) {
+                    Ok(frames) => {
+                        state.handle_frames(frames);
+                        continue;
+                    }
+                    Err(e) => Err(e),
+                },
+                Ok(None) => state.finish(),
+                Err(e) => Err(e.into()),
+            };
+
+            // The body is over, or broken
+            state.resp = None;
+            return Some((item, state));
+        }
+    })
+}
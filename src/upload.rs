@@ -0,0 +1,80 @@
+//! Files attached to a prompt, uploaded the way the web app does.
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::client::check_status;
+use crate::error::{GeminiError, Result};
+
+/// Endpoint the web app uploads attachments to.
+pub const DEFAULT_UPLOAD_URL: &str = "https://content-push.googleapis.com/upload/";
+
+/// Headers identifying the Gemini storage to the upload endpoint.
+const PUSH_ID: &str = "feeds/mcudyrk2a4khkz";
+const TENANT_ID: &str = "bard-storage";
+
+/// An uploaded file, sent with the next prompt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Reference returned by the upload endpoint, e.g. `/contrib_service/ttl_1d/...`
+    pub file_id: String,
+    /// File name shown next to the prompt
+    pub name: String,
+}
+
+/// Uploads `bytes` with Google's resumable upload protocol: one request
+/// opens the upload and returns its URL, a second one sends the file.
+pub(crate) async fn upload(
+    client: &reqwest::Client,
+    url: &Url,
+    name: &str,
+    bytes: Vec<u8>,
+) -> Result<Attachment> {
+    let start = client
+        .post(url.clone())
+        .header("Push-ID", PUSH_ID)
+        .header("X-Tenant-Id", TENANT_ID)
+        .header("X-Goog-Upload-Protocol", "resumable")
+        .header("X-Goog-Upload-Command", "start")
+        .header("X-Goog-Upload-Header-Content-Length", bytes.len())
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded;charset=UTF-8"),
+        )
+        .body(format!("File name: {name}"))
+        .send()
+        .await?;
+    let start = check_status(start)?;
+
+    let session_url = start
+        .headers()
+        .get("X-Goog-Upload-URL")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| url.join(value).ok())
+        .ok_or_else(|| {
+            GeminiError::unexpected_payload("no X-Goog-Upload-URL in the upload response", "")
+        })?;
+
+    let finish = client
+        .post(session_url)
+        .header("Push-ID", PUSH_ID)
+        .header("X-Tenant-Id", TENANT_ID)
+        .header("X-Goog-Upload-Command", "upload, finalize")
+        .header("X-Goog-Upload-Offset", 0)
+        .body(bytes)
+        .send()
+        .await?;
+    let file_id = check_status(finish)?.text().await?.trim().to_string();
+    if file_id.is_empty() {
+        return Err(GeminiError::unexpected_payload(
+            "the upload endpoint returned no file reference",
+            "",
+        ));
+    }
+
+    Ok(Attachment {
+        file_id,
+        name: name.to_string(),
+    })
+}
@@ -0,0 +1,178 @@
+//! Resuming a batch from the records already in its output file.
+
+mod common;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use bard_rs::batch::{self, BatchError, BatchRecord, BatchSummary};
+use bard_rs::{ConversationIds, GeminiError, GeminiResponse};
+use common::{MockGemini, Sent};
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bard-rs-batch-{}-{name}", std::process::id()))
+}
+
+fn record(line: usize, conversation: Option<&str>, conversation_id: &str) -> String {
+    let record = BatchRecord {
+        line,
+        id: None,
+        conversation: conversation.map(str::to_string),
+        prompt: format!("prompt {line}"),
+        response: Some(GeminiResponse {
+            content: "earlier answer".to_string(),
+            conversation_ids: ConversationIds {
+                conversation_id: conversation_id.to_string(),
+                response_id: "r_old".to_string(),
+                choice_id: "rc_old".to_string(),
+            },
+            ..Default::default()
+        }),
+        error: None,
+    };
+    serde_json::to_string(&record).unwrap()
+}
+
+#[tokio::test]
+async fn resumes_after_the_recorded_lines() {
+    let input = temp_path("input.jsonl");
+    let output = temp_path("output.jsonl");
+    std::fs::write(
+        &input,
+        [
+            r#"{"prompt": "prompt 1"}"#,
+            r#"{"prompt": "prompt 2", "conversation": "a"}"#,
+            "",
+            r#"{"prompt": "prompt 4", "conversation": "a"}"#,
+            r#"{"prompt": "prompt 5", "conversation": "b"}"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+    // the record of line 4 was cut short by a crash
+    let truncated = &record(4, Some("a"), "c_old")[..30];
+    std::fs::write(
+        &output,
+        format!(
+            "{}\n{}\n{truncated}",
+            record(1, None, "c_first"),
+            record(2, Some("a"), "c_old")
+        ),
+    )
+    .unwrap();
+
+    let (mock, mut client) = MockGemini::start().await;
+    let summary = batch::run(&mut client, &input, &output, Duration::ZERO, |_| {})
+        .await
+        .unwrap();
+
+    assert_eq!(
+        summary,
+        BatchSummary {
+            answered: 2,
+            failed: 0,
+            skipped: 2,
+        }
+    );
+    assert_eq!(
+        mock.sent(),
+        [
+            Sent {
+                prompt: "prompt 4".to_string(),
+                conversation_id: "c_old".to_string(),
+            },
+            Sent {
+                prompt: "prompt 5".to_string(),
+                conversation_id: String::new(),
+            },
+        ]
+    );
+
+    let written = std::fs::read_to_string(&output).unwrap();
+    let lines: Vec<&str> = written.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[2], truncated);
+    let records: Vec<BatchRecord> = lines[3..]
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records[0].line, 4);
+    assert_eq!(records[1].line, 5);
+    assert_eq!(
+        records[1]
+            .response
+            .as_ref()
+            .unwrap()
+            .conversation_ids
+            .conversation_id,
+        "c_2"
+    );
+
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+}
+
+#[tokio::test]
+async fn refused_lines_are_sent_again_and_a_rate_limit_stops_the_run() {
+    let input = temp_path("refused-input.jsonl");
+    let output = temp_path("refused-output.jsonl");
+    std::fs::write(
+        &input,
+        [
+            r#"{"prompt": "prompt 1"}"#,
+            r#"{"prompt": "prompt 2"}"#,
+            r#"{"prompt": "prompt 3"}"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+    let failed = |line: usize, kind: &str| {
+        serde_json::to_string(&BatchRecord {
+            line,
+            id: None,
+            conversation: None,
+            prompt: format!("prompt {line}"),
+            response: None,
+            error: Some(BatchError {
+                kind: kind.to_string(),
+                message: String::new(),
+            }),
+        })
+        .unwrap()
+    };
+    std::fs::write(
+        &output,
+        format!(
+            "{}\n{}\n",
+            failed(1, "captcha"),
+            failed(2, "unexpected_payload")
+        ),
+    )
+    .unwrap();
+
+    let (mock, mut client) = MockGemini::start().await;
+    mock.fail_next(StatusCode::TOO_MANY_REQUESTS);
+    let err = batch::run(&mut client, &input, &output, Duration::ZERO, |_| {})
+        .await
+        .unwrap_err();
+    assert!(matches!(err, GeminiError::RateLimited { .. }));
+    assert_eq!(std::fs::read_to_string(&output).unwrap().lines().count(), 2);
+
+    let summary = batch::run(&mut client, &input, &output, Duration::ZERO, |_| {})
+        .await
+        .unwrap();
+    assert_eq!(
+        summary,
+        BatchSummary {
+            answered: 2,
+            failed: 0,
+            skipped: 1,
+        }
+    );
+    let prompts: Vec<String> = mock.sent().into_iter().map(|sent| sent.prompt).collect();
+    assert_eq!(prompts, ["prompt 1", "prompt 1", "prompt 3"]);
+
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+}
@@ -0,0 +1,324 @@
+//! A local stand-in for the Gemini web app, answering every prompt and
+//! recording what it was sent.
+//!
+//! Every test binary uses a different part of it.
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use bard_rs::{GeminiClient, GeminiClientBuilder, RateLimit, RateLimiter, RetryPolicy};
+
+pub const HOMEPAGE: &str = r#"<script>WIZ_global_data={"SNlM0e":"AFtoken","cfb2h":"boq_assistant-bard-web-server_20260101.01_p0","FdrFJe":"-4242"}</script>"#;
+
+/// A prompt as the mock received it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sent {
+    pub prompt: String,
+    /// Conversation the prompt continued, empty for a new one
+    pub conversation_id: String,
+}
+
+/// A file uploaded with the resumable protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    /// From the `File name: ...` body of the request opening the upload
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Any request the mock received.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// The `Cookie` header
+    pub cookie: String,
+}
+
+#[derive(Clone, Default)]
+pub struct MockGemini {
+    pub sent: Arc<Mutex<Vec<Sent>>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+    /// `f.req` message of every prompt
+    messages: Arc<Mutex<Vec<serde_json::Value>>>,
+    uploads: Arc<Mutex<Vec<Upload>>>,
+    /// Versions of the next answer, one frame each
+    script: Arc<Mutex<Vec<String>>>,
+    /// Statuses the next prompts are refused with
+    failures: Arc<Mutex<VecDeque<StatusCode>>>,
+    /// Replaces [`HOMEPAGE`]
+    homepage: Arc<Mutex<Option<String>>>,
+    /// `__Secure-1PSIDTS` the homepage and the prompts are refused without
+    required_psidts: Arc<Mutex<Option<String>>>,
+    /// Statuses the next rotations are refused with
+    rotate_failures: Arc<Mutex<VecDeque<StatusCode>>>,
+    rotations: Arc<Mutex<usize>>,
+}
+
+impl MockGemini {
+    /// Serves the mock on a free port and returns a client talking to it.
+    pub async fn start() -> (Self, GeminiClient) {
+        let mock = Self::default();
+        let base = mock.serve().await;
+        let client = mock.builder(&base).build().await.unwrap();
+        (mock, client)
+    }
+
+    /// Serves the mock on a free port and returns its base URL.
+    pub async fn serve(&self) -> String {
+        let app = Router::new()
+            .route("/", get(homepage))
+            .route("/generate", post(generate))
+            .route("/rotate", post(rotate))
+            .route("/upload/", post(start_upload))
+            .route("/upload/{session}", post(finish_upload))
+            .with_state(self.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/")
+    }
+
+    /// A client of the mock served at `base`, not built yet.
+    pub fn builder(&self, base: &str) -> GeminiClientBuilder {
+        GeminiClient::builder()
+            .psid("psid")
+            .psidts("psidts")
+            .base_url(base)
+            .stream_generate_path("generate")
+            .rotate_cookies_url(format!("{base}rotate"))
+            .upload_url(format!("{base}upload/"))
+            .rotate_interval(None)
+            .retry_policy(RetryPolicy::none())
+            .rate_limiter(RateLimiter::new(RateLimit::unlimited()))
+    }
+
+    pub fn sent(&self) -> Vec<Sent> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// The `f.req` message of every prompt: `[prompt, null, conversation ids]`.
+    pub fn messages(&self) -> Vec<serde_json::Value> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Files uploaded so far, finished or not.
+    pub fn uploads(&self) -> Vec<Upload> {
+        self.uploads.lock().unwrap().clone()
+    }
+
+    /// Every request received at `path`.
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|r| r.path == path)
+            .cloned()
+            .collect()
+    }
+
+    /// Streams the next answer as `versions`, the way Gemini repeats the
+    /// answer so far in every frame.
+    pub fn next_answer(&self, versions: &[&str]) {
+        *self.script.lock().unwrap() = versions.iter().map(|v| v.to_string()).collect();
+    }
+
+    /// Refuses the next prompt with `status`.
+    pub fn fail_next(&self, status: StatusCode) {
+        self.failures.lock().unwrap().push_back(status);
+    }
+
+    pub fn set_homepage(&self, homepage: &str) {
+        *self.homepage.lock().unwrap() = Some(homepage.to_string());
+    }
+
+    /// Answers `401` to a session without this `__Secure-1PSIDTS`.
+    pub fn require_psidts(&self, psidts: &str) {
+        *self.required_psidts.lock().unwrap() = Some(psidts.to_string());
+    }
+
+    /// Refuses the next rotation with `status`.
+    pub fn fail_next_rotation(&self, status: StatusCode) {
+        self.rotate_failures.lock().unwrap().push_back(status);
+    }
+
+    fn record(&self, uri: &Uri, query: HashMap<String, String>, headers: &HeaderMap) {
+        let cookie = headers
+            .get(header::COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        self.requests.lock().unwrap().push(Request {
+            path: uri.path().to_string(),
+            query,
+            cookie: cookie.to_string(),
+        });
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(psidts) = self.required_psidts.lock().unwrap().clone() else {
+            return true;
+        };
+        headers
+            .get(header::COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|cookie| {
+                cookie
+                    .split("; ")
+                    .any(|pair| pair == format!("__Secure-1PSIDTS={psidts}"))
+            })
+    }
+}
+
+async fn homepage(
+    State(mock): State<MockGemini>,
+    uri: Uri,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> (StatusCode, String) {
+    mock.record(&uri, query, &headers);
+    if !mock.authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, String::new());
+    }
+    let homepage = mock.homepage.lock().unwrap().clone();
+    (
+        StatusCode::OK,
+        homepage.unwrap_or_else(|| HOMEPAGE.to_string()),
+    )
+}
+
+/// Hands out `__Secure-1PSIDTS=rotated-N` for the Nth rotation.
+async fn rotate(
+    State(mock): State<MockGemini>,
+    uri: Uri,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    mock.record(&uri, query, &headers);
+    if let Some(status) = mock.rotate_failures.lock().unwrap().pop_front() {
+        return status.into_response();
+    }
+    let n = {
+        let mut rotations = mock.rotations.lock().unwrap();
+        *rotations += 1;
+        *rotations
+    };
+    (
+        [
+            (header::SET_COOKIE, "NID=511; Path=/; HttpOnly".to_string()),
+            (
+                header::SET_COOKIE,
+                format!("__Secure-1PSIDTS=rotated-{n}; Domain=.google.com; Path=/; Secure"),
+            ),
+        ],
+        "[]",
+    )
+        .into_response()
+}
+
+/// Opens upload N, whose URL is relative like Google's.
+async fn start_upload(
+    State(mock): State<MockGemini>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if header("X-Goog-Upload-Protocol") != Some("resumable")
+        || header("X-Goog-Upload-Command") != Some("start")
+        || header("Push-ID").is_none()
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let name = body.strip_prefix("File name: ").unwrap_or_default();
+    let n = {
+        let mut uploads = mock.uploads.lock().unwrap();
+        uploads.push(Upload {
+            name: name.to_string(),
+            bytes: Vec::new(),
+        });
+        uploads.len()
+    };
+    [("X-Goog-Upload-URL", format!("/upload/session-{n}"))].into_response()
+}
+
+/// Receives the file of an open upload and returns its reference.
+async fn finish_upload(
+    State(mock): State<MockGemini>,
+    axum::extract::Path(session): axum::extract::Path<String>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let n: usize = session.strip_prefix("session-").unwrap().parse().unwrap();
+    if header("X-Goog-Upload-Command") != Some("upload, finalize")
+        || header("X-Goog-Upload-Offset") != Some("0")
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    mock.uploads.lock().unwrap()[n - 1].bytes = body.to_vec();
+    format!("/contrib_service/ttl_1d/file-{n}\n").into_response()
+}
+
+/// Answers "answer N", or the versions given to [`MockGemini::next_answer`],
+/// with response id `r_N`, keeping the conversation id it was sent or
+/// starting `c_N`.
+async fn generate(
+    State(mock): State<MockGemini>,
+    uri: Uri,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: String,
+) -> (StatusCode, String) {
+    mock.record(&uri, query, &headers);
+    if !mock.authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, String::new());
+    }
+    let f_req = url::form_urlencoded::parse(body.as_bytes())
+        .find(|(key, _)| key == "f.req")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    let outer: serde_json::Value = serde_json::from_str(&f_req).unwrap();
+    let message: serde_json::Value = serde_json::from_str(outer[1].as_str().unwrap()).unwrap();
+    mock.messages.lock().unwrap().push(message.clone());
+    let sent = Sent {
+        prompt: message[0][0].as_str().unwrap().to_string(),
+        conversation_id: message[2][0].as_str().unwrap().to_string(),
+    };
+
+    let n = {
+        let mut log = mock.sent.lock().unwrap();
+        log.push(sent.clone());
+        log.len()
+    };
+    if let Some(status) = mock.failures.lock().unwrap().pop_front() {
+        return (status, String::new());
+    }
+    let conversation_id = if sent.conversation_id.is_empty() {
+        format!("c_{n}")
+    } else {
+        sent.conversation_id
+    };
+    let mut versions = std::mem::take(&mut *mock.script.lock().unwrap());
+    if versions.is_empty() {
+        versions.push(format!("answer {n}"));
+    }
+
+    let mut body = ")]}'\n\n".to_string();
+    for text in versions {
+        let inner = serde_json::json!([
+            null,
+            [conversation_id, format!("r_{n}")],
+            [[sent.prompt, 1]],
+            null,
+            [[format!("rc_{n}"), [text]]]
+        ]);
+        let frame = serde_json::json!([["wrb.fr", null, inner.to_string()]]).to_string();
+        body.push_str(&format!("{}\n{frame}\n", frame.len()));
+    }
+    (StatusCode::OK, body)
+}
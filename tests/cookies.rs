@@ -0,0 +1,136 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use bard_rs::{Cookies, GeminiError};
+use common::MockGemini;
+
+const COOKIES_TXT: &str = "# Netscape HTTP Cookie File
+# This is a generated file! Do not edit.
+
+.google.com\tTRUE\t/\tTRUE\t4102444800\t__Secure-1PSID\tg.a000psid
+#HttpOnly_.google.com\tTRUE\t/\tTRUE\t4102444800\t__Secure-1PSIDTS\tsidts-CjEB
+gemini.google.com\tFALSE\t/\tTRUE\t0\t_ga\tGA1.1.123
+.google.com\tTRUE\t/\tTRUE\t1000000000\tNID\texpired
+.youtube.com\tTRUE\t/\tTRUE\t4102444800\tVISITOR_INFO1_LIVE\tnope
+notgoogle.com\tTRUE\t/\tTRUE\t4102444800\tSID\tnope
+";
+
+#[test]
+fn netscape_keeps_live_google_cookies() {
+    let cookies = Cookies::from_netscape(COOKIES_TXT);
+
+    assert_eq!(cookies.get("__Secure-1PSID"), Some("g.a000psid"));
+    assert_eq!(cookies.get("__Secure-1PSIDTS"), Some("sidts-CjEB"));
+    assert_eq!(cookies.get("_ga"), Some("GA1.1.123"));
+    assert_eq!(cookies.iter().count(), 3);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn firefox_sqlite() {
+    let path = std::env::temp_dir().join(format!("bard-rs-cookies-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE moz_cookies (id INTEGER PRIMARY KEY, name TEXT, value TEXT, host TEXT,
+                expiry INTEGER, lastAccessed INTEGER);
+             INSERT INTO moz_cookies (name, value, host, expiry, lastAccessed) VALUES
+                ('__Secure-1PSID', 'g.a000psid', '.google.com', 4102444800, 1),
+                ('__Secure-1PSIDTS', 'sidts-CjEB', '.google.com', 4102444800000, 2),
+                ('SID', 'other', '.example.com', 4102444800, 3),
+                ('NID', 'expired', '.google.com', 1000000000, 4);",
+        )
+        .unwrap();
+    }
+
+    let cookies = Cookies::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(cookies.get("__Secure-1PSID"), Some("g.a000psid"));
+    assert_eq!(cookies.get("__Secure-1PSIDTS"), Some("sidts-CjEB"));
+    assert_eq!(cookies.iter().count(), 2);
+}
+
+#[tokio::test]
+async fn rotation_updates_the_cookie_header() {
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    let rotated = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&rotated);
+    let mut client = mock
+        .builder(&base)
+        .on_cookies_rotated(move |psidts| seen.lock().unwrap().push(psidts.to_string()))
+        .build()
+        .await
+        .unwrap();
+
+    assert!(client.rotate_cookies().await.unwrap());
+    let cookies = client.cookies();
+    assert_eq!(cookies.get("__Secure-1PSIDTS"), Some("rotated-1"));
+    // only __Secure-1PSIDTS is taken from the response
+    assert_eq!(cookies.get("NID"), None);
+    assert_eq!(*rotated.lock().unwrap(), ["rotated-1"]);
+
+    // too soon after the last one, Google isn't asked again
+    assert!(!client.rotate_cookies().await.unwrap());
+    assert_eq!(mock.requests("/rotate").len(), 1);
+
+    client.ask("hi").await.unwrap();
+    let cookie = &mock.requests("/generate")[0].cookie;
+    assert!(cookie.contains("__Secure-1PSIDTS=rotated-1"), "{cookie}");
+    assert!(cookie.contains("__Secure-1PSID=psid"), "{cookie}");
+}
+
+#[tokio::test]
+async fn refused_rotation_is_invalid_cookies() {
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    let client = mock.builder(&base).build().await.unwrap();
+    mock.fail_next_rotation(StatusCode::UNAUTHORIZED);
+
+    let err = client.rotate_cookies().await.unwrap_err();
+    assert!(matches!(err, GeminiError::InvalidCookies(_)), "{err:?}");
+    assert_eq!(client.cookies().get("__Secure-1PSIDTS"), Some("psidts"));
+}
+
+#[tokio::test]
+async fn build_rotates_expired_cookies_once() {
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    mock.require_psidts("rotated-1");
+
+    let client = mock.builder(&base).build().await.unwrap();
+    assert_eq!(client.cookies().get("__Secure-1PSIDTS"), Some("rotated-1"));
+    let homepage = mock.requests("/");
+    assert_eq!(homepage.len(), 2);
+    assert!(homepage[0].cookie.contains("__Secure-1PSIDTS=psidts"));
+
+    // the rotated cookie is rejected too, no second rotation
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    mock.require_psidts("never");
+    let err = mock.builder(&base).build().await.err().unwrap();
+    assert!(matches!(err, GeminiError::InvalidCookies(_)), "{err:?}");
+    assert_eq!(mock.requests("/rotate").len(), 1);
+}
+
+#[tokio::test]
+async fn timer_rotates_in_the_background() {
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    let client = mock
+        .builder(&base)
+        .rotate_interval(Some(Duration::from_millis(50)))
+        .build()
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(client.cookies().get("__Secure-1PSIDTS"), Some("rotated-1"));
+    // later ticks fall within the minimum gap
+    assert_eq!(mock.requests("/rotate").len(), 1);
+}
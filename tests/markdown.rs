@@ -0,0 +1,114 @@
+//! Terminal rendering of answers, compared with the colors stripped.
+
+use bard_rs::markdown::{code_blocks, render, MarkdownStream};
+use regex::Regex;
+
+fn plain(text: &str) -> String {
+    Regex::new("\x1b\\[[0-9;]*m")
+        .unwrap()
+        .replace_all(text, "")
+        .into_owned()
+}
+
+#[test]
+fn headings_and_emphasis_lose_their_markup() {
+    let out = plain(&render("# Title\n\nSome **bold** and `code`.", 80));
+    assert_eq!(out, "Title\n\nSome bold and code.\n");
+}
+
+#[test]
+fn list_items_wrap_under_their_text() {
+    let out = plain(&render(
+        "1. one two three four five six\n2. seven\n   - eight",
+        20,
+    ));
+    assert_eq!(
+        out,
+        "1. one two three\n   four five six\n2. seven\n   • eight\n"
+    );
+}
+
+#[test]
+fn table_columns_are_aligned() {
+    let markdown = "| Name | Age |\n|:-----|----:|\n| Alice | 30 |\n| Bob | 4 |";
+    let out = plain(&render(markdown, 80));
+    assert_eq!(
+        out,
+        "│ Name  │ Age │\n├───────┼─────┤\n│ Alice │  30 │\n│ Bob   │   4 │\n"
+    );
+}
+
+#[test]
+fn code_blocks_are_not_wrapped() {
+    let code = "let answer = \"a line of code that is longer than the width\";";
+    let out = plain(&render(&format!("```rust\n{code}\n```"), 20));
+    assert_eq!(out, format!("─── rust\n{code}\n"));
+}
+
+#[test]
+fn stream_waits_for_the_end_of_a_code_fence() {
+    let markdown = "Intro\n\n```sh\necho one\n\necho two\n```\n\nOutro";
+    let mut stream = MarkdownStream::new(80);
+
+    assert_eq!(
+        plain(&stream.push("Intro\n\n```sh\necho one\n\n")),
+        "Intro\n"
+    );
+    assert_eq!(stream.push("echo two\n"), "");
+    let rest = plain(&format!(
+        "{}{}",
+        stream.push("```\n\nOutro"),
+        stream.finish()
+    ));
+    assert_eq!(rest, "\n─── sh\necho one\n\necho two\n\nOutro\n");
+    assert_eq!(format!("Intro\n{rest}"), plain(&render(markdown, 80)));
+}
+
+#[test]
+fn code_blocks_with_their_language() {
+    let markdown =
+        "Try:\n\n```rust,ignore\nfn main() {}\n```\n\nor\n\n    plain\n\n```\nno language\n```";
+    let blocks = code_blocks(markdown);
+
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[0].language, "rust");
+    assert_eq!(blocks[0].code, "fn main() {}\n");
+    assert_eq!(blocks[1].language, "");
+    assert_eq!(blocks[1].code, "plain\n");
+    assert_eq!(blocks[2].code, "no language\n");
+}
+
+#[test]
+fn extension_follows_the_fence_language() {
+    let extension = |language: &str| {
+        let blocks = code_blocks(&format!("```{language}\nx\n```"));
+        blocks[0].extension().to_string()
+    };
+
+    assert_eq!(extension("rust"), "rs");
+    assert_eq!(extension("Python"), "py");
+    assert_eq!(extension("bash"), "sh");
+    assert_eq!(extension("haskell"), "hs");
+    assert_eq!(extension(""), "txt");
+    assert_eq!(extension("no-such-language"), "txt");
+}
+
+#[test]
+fn stream_prints_lines_as_they_end() {
+    let mut stream = MarkdownStream::new(80);
+
+    assert_eq!(
+        plain(&stream.push("A long paragraph\nstill going")),
+        "A long paragraph\n"
+    );
+    assert_eq!(
+        plain(&stream.push(" on\n\n| a | b |\n|---|---|\n| 1 | 2 |\n")),
+        "still going on\n"
+    );
+    // the table is aligned once it ends, a nested item goes with its parent
+    assert_eq!(
+        plain(&stream.push("\n- item\n    - nested\n- next")),
+        "\n│ a │ b │\n├───┼───┤\n│ 1 │ 2 │\n\n• item\n  • nested\n"
+    );
+    assert_eq!(plain(&stream.finish()), "• next\n");
+}
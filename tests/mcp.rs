@@ -0,0 +1,84 @@
+//! A scripted MCP session, one JSON-RPC message per line.
+
+mod common;
+
+use std::sync::Arc;
+
+use bard_rs::mcp::{self, PROTOCOL_VERSION};
+use bard_rs::server::Backend;
+use common::MockGemini;
+use serde_json::{json, Value};
+
+async fn session(requests: &[&str]) -> (MockGemini, Vec<Value>) {
+    let (mock, client) = MockGemini::start().await;
+    let input = requests.join("\n");
+    let mut output = Vec::new();
+    mcp::serve(
+        Arc::new(Backend::new(client)),
+        input.as_bytes(),
+        &mut output,
+    )
+    .await
+    .unwrap();
+
+    let replies = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    (mock, replies)
+}
+
+#[tokio::test]
+async fn answers_requests_but_not_notifications() {
+    let (mock, replies) = session(&[
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"protocolVersion": "2025-03-26"}}"#,
+        r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#,
+        r#"{"jsonrpc": "2.0", "id": 2, "method": "tools/list"}"#,
+        "{not json",
+        r#"{"jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": {"name": "gemini_draw"}}"#,
+        r#"{"jsonrpc": "2.0", "id": 4, "method": "tools/call", "params": {"name": "gemini_ask", "arguments": {"prompt": "Hi"}}}"#,
+    ])
+    .await;
+
+    assert_eq!(replies.len(), 5);
+    assert_eq!(replies[0]["id"], 1);
+    assert_eq!(replies[0]["result"]["protocolVersion"], "2025-03-26");
+    assert_eq!(replies[0]["result"]["serverInfo"]["name"], "bard-rs");
+
+    let tools: Vec<&str> = replies[1]["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| tool["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(tools, ["gemini_ask", "gemini_reset"]);
+
+    assert_eq!(replies[2]["id"], Value::Null);
+    assert_eq!(replies[2]["error"]["code"], -32700);
+
+    assert_eq!(replies[3]["id"], 3);
+    assert_eq!(replies[3]["error"]["code"], -32602);
+    assert_eq!(
+        replies[3]["error"]["message"],
+        "unknown tool \"gemini_draw\""
+    );
+
+    assert_eq!(
+        replies[4]["result"],
+        json!({ "content": [{ "type": "text", "text": "answer 1" }], "isError": false })
+    );
+    assert_eq!(mock.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn unknown_protocol_version_gets_the_default() {
+    let (_, replies) = session(&[
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"protocolVersion": "1999-01-01"}}"#,
+        r#"{"jsonrpc": "2.0", "id": 2, "method": "initialize", "params": {}}"#,
+    ])
+    .await;
+
+    assert_eq!(replies[0]["result"]["protocolVersion"], PROTOCOL_VERSION);
+    assert_eq!(replies[1]["result"]["protocolVersion"], PROTOCOL_VERSION);
+}
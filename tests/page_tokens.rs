@@ -0,0 +1,49 @@
+//! Values scraped from the Gemini homepage and sent with every prompt.
+
+mod common;
+
+use bard_rs::client::DEFAULT_BUILD_LABEL;
+use common::MockGemini;
+
+#[tokio::test]
+async fn scraped_build_label_and_session_id_reach_the_rpc() {
+    let (mock, mut client) = MockGemini::start().await;
+    assert_eq!(
+        client.build_label(),
+        "boq_assistant-bard-web-server_20260101.01_p0"
+    );
+
+    client.ask("hi").await.unwrap();
+    let query = &mock.requests("/generate")[0].query;
+    assert_eq!(
+        query.get("bl").map(String::as_str),
+        Some("boq_assistant-bard-web-server_20260101.01_p0")
+    );
+    assert_eq!(query.get("f.sid").map(String::as_str), Some("-4242"));
+}
+
+#[tokio::test]
+async fn homepage_without_them_falls_back() {
+    let homepage = r#"<script>WIZ_global_data={"SNlM0e":"AFtoken"}</script>"#;
+
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    mock.set_homepage(homepage);
+    let mut client = mock
+        .builder(&base)
+        .build_label("boq_configured")
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(client.build_label(), "boq_configured");
+    client.ask("hi").await.unwrap();
+    let query = &mock.requests("/generate")[0].query;
+    assert_eq!(query.get("bl").map(String::as_str), Some("boq_configured"));
+    assert_eq!(query.get("f.sid"), None);
+
+    let mock = MockGemini::default();
+    let base = mock.serve().await;
+    mock.set_homepage(homepage);
+    let client = mock.builder(&base).build().await.unwrap();
+    assert_eq!(client.build_label(), DEFAULT_BUILD_LABEL);
+}
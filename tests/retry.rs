@@ -0,0 +1,118 @@
+//! Waits chosen by [`RetryPolicy::delay_for`], checked against the bounds of
+//! the jitter since it can shorten a wait by up to half.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+use bard_rs::{GeminiError, RetryPolicy};
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 10,
+        base_delay: Duration::from_secs(2),
+        max_delay: Duration::from_secs(60),
+    }
+}
+
+fn rate_limited(retry_after: Option<u64>) -> GeminiError {
+    GeminiError::RateLimited {
+        retry_after: retry_after.map(Duration::from_secs),
+    }
+}
+
+fn assert_between(delay: Option<Duration>, min: u64, max: u64) {
+    let delay = delay.expect("should retry");
+    assert!(
+        delay >= Duration::from_secs(min) && delay <= Duration::from_secs(max),
+        "{delay:?} is not within {min}s..={max}s"
+    );
+}
+
+/// Error of a response whose body stops before its `Content-Length`.
+async fn body_error() -> GeminiError {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nhalf")
+            .unwrap();
+    });
+
+    let resp = reqwest::get(format!("http://{addr}/")).await.unwrap();
+    let err = resp.bytes().await.unwrap_err();
+    server.join().unwrap();
+    GeminiError::from(err)
+}
+
+#[test]
+fn backoff_doubles_up_to_max_delay() {
+    let policy = policy();
+
+    assert_between(policy.delay_for(0, &rate_limited(None)), 1, 2);
+    assert_between(policy.delay_for(2, &rate_limited(None)), 4, 8);
+    assert_between(policy.delay_for(5, &rate_limited(None)), 30, 60);
+    assert_between(policy.delay_for(9, &rate_limited(None)), 30, 60);
+}
+
+#[test]
+fn captcha_waits_max_delay_from_the_first_retry() {
+    assert_between(policy().delay_for(0, &GeminiError::Captcha), 30, 60);
+}
+
+#[test]
+fn retry_after_wins_when_longer() {
+    let policy = policy();
+
+    assert_eq!(
+        policy.delay_for(0, &rate_limited(Some(120))),
+        Some(Duration::from_secs(120))
+    );
+    let server_error = GeminiError::ServerError {
+        status: 503,
+        retry_after: Some(Duration::from_secs(90)),
+    };
+    assert_eq!(
+        policy.delay_for(0, &server_error),
+        Some(Duration::from_secs(90))
+    );
+    // a shorter Retry-After doesn't cut the backoff
+    assert_between(policy.delay_for(4, &rate_limited(Some(1))), 16, 32);
+}
+
+#[tokio::test]
+async fn errors_after_the_request_was_accepted_are_not_retried() {
+    let policy = policy();
+
+    assert_eq!(
+        policy.delay_for(0, &GeminiError::InvalidCookies("expired".into())),
+        None
+    );
+    assert_eq!(policy.delay_for(0, &body_error().await), None);
+}
+
+#[test]
+fn max_retries_zero_never_retries() {
+    let policy = RetryPolicy {
+        max_retries: 0,
+        ..policy()
+    };
+
+    assert_eq!(policy.delay_for(0, &GeminiError::Captcha), None);
+    assert_eq!(policy.delay_for(0, &rate_limited(Some(5))), None);
+    assert_eq!(RetryPolicy::none().delay_for(0, &rate_limited(None)), None);
+}
+
+#[test]
+fn stops_after_max_retries() {
+    let policy = RetryPolicy {
+        max_retries: 2,
+        ..policy()
+    };
+
+    assert!(policy.delay_for(1, &rate_limited(None)).is_some());
+    assert_eq!(policy.delay_for(2, &rate_limited(None)), None);
+}
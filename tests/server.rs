@@ -0,0 +1,98 @@
+//! Streamed answers of the OpenAI and Ollama APIs, served from a mock Gemini.
+
+mod common;
+
+use std::sync::Arc;
+
+use bard_rs::server::{self, Backend};
+use common::MockGemini;
+use serde_json::{json, Value};
+
+const FINAL: &str = "Rust is fast[1] and safe. It has no garbage collector. [1] rust-lang.org";
+
+/// Serves the APIs with the next answer rewriting its start halfway through.
+async fn start() -> String {
+    let (mock, client) = MockGemini::start().await;
+    mock.next_answer(&[
+        "Rust is fast and safe. It has no garbage",
+        "Rust is fast[1] and safe. It has no garbage collector.",
+        FINAL,
+    ]);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let backend = Arc::new(Backend::new(client));
+    tokio::spawn(async move { server::serve(backend, listener, None).await.unwrap() });
+    format!("http://{addr}")
+}
+
+async fn post(url: String, body: Value) -> String {
+    reqwest::Client::new()
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn openai_stream_ends_with_the_final_answer() {
+    let base = start().await;
+    let body = post(
+        format!("{base}/v1/chat/completions"),
+        json!({ "stream": true, "messages": [{ "role": "user", "content": "Why Rust?" }] }),
+    )
+    .await;
+
+    let data: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(data.last(), Some(&"[DONE]"));
+    let chunks: Vec<Value> = data[..data.len() - 1]
+        .iter()
+        .map(|chunk| serde_json::from_str(chunk).unwrap())
+        .collect();
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert!(text.ends_with(" collector. [1] rust-lang.org"), "{text}");
+    let last = &chunks[chunks.len() - 1]["choices"][0];
+    assert_eq!(last["finish_reason"], "stop");
+    assert_eq!(last["delta"], json!({}));
+}
+
+#[tokio::test]
+async fn ollama_stream_ends_with_the_final_answer() {
+    for (path, request) in [
+        (
+            "/api/chat",
+            json!({ "messages": [{ "role": "user", "content": "Why Rust?" }] }),
+        ),
+        ("/api/generate", json!({ "prompt": "Why Rust?" })),
+    ] {
+        let base = start().await;
+        let body = post(format!("{base}{path}"), request).await;
+
+        let lines: Vec<Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let text: String = lines
+            .iter()
+            .filter_map(|line| {
+                line["message"]["content"]
+                    .as_str()
+                    .or(line["response"].as_str())
+            })
+            .collect();
+        assert!(
+            text.ends_with(" collector. [1] rust-lang.org"),
+            "{path}: {text}"
+        );
+        assert_eq!(lines.last().unwrap()["done"], true);
+    }
+}
@@ -0,0 +1,71 @@
+//! Deltas handed out by `ask_stream`, including when Gemini rewrites text it
+//! already sent.
+
+mod common;
+
+use std::pin::pin;
+
+use bard_rs::stream::unstreamed;
+use bard_rs::StreamEvent;
+use common::MockGemini;
+use futures_util::StreamExt;
+
+#[test]
+fn unstreamed_is_the_tail_of_a_growing_answer() {
+    assert_eq!(unstreamed("Hello", "Hello world"), " world");
+    assert_eq!(unstreamed("", "Hello"), "Hello");
+    assert_eq!(unstreamed("Hello", "Hello"), "");
+}
+
+#[test]
+fn unstreamed_continues_after_a_rewrite() {
+    let streamed = "Rust is fast and safe. It has no garbage";
+    let content = "Rust is fast[1] and safe. It has no garbage collector.";
+    assert_eq!(unstreamed(streamed, content), " collector.");
+
+    // nothing of the end left, everything after the shared start is sent
+    assert_eq!(
+        unstreamed(
+            "An image: [placeholder]",
+            "An image: ![fox](https://x/fox.png)"
+        ),
+        "![fox](https://x/fox.png)"
+    );
+    assert_eq!(unstreamed("Café au lait", "Café noir"), "noir");
+}
+
+#[tokio::test]
+async fn deltas_go_on_after_a_rewrite() {
+    let (mock, mut client) = MockGemini::start().await;
+    mock.next_answer(&[
+        "Rust is fast",
+        "Rust is fast and safe. It has no garbage",
+        "Rust is fast",
+        "Rust is fast[1] and safe. It has no garbage collector.",
+        "Rust is fast[1] and safe. It has no garbage collector. [1] rust-lang.org",
+    ]);
+
+    let mut stream = pin!(client.ask_stream("Why Rust?").await.unwrap());
+    let mut deltas = Vec::new();
+    let mut done = None;
+    while let Some(event) = stream.next().await {
+        match event.unwrap() {
+            StreamEvent::Delta(delta) => deltas.push(delta),
+            StreamEvent::Done(response) => done = Some(response),
+        }
+    }
+
+    assert_eq!(
+        deltas,
+        [
+            "Rust is fast",
+            " and safe. It has no garbage",
+            " collector.",
+            " [1] rust-lang.org"
+        ]
+    );
+    assert_eq!(
+        done.unwrap().content,
+        "Rust is fast[1] and safe. It has no garbage collector. [1] rust-lang.org"
+    );
+}
@@ -0,0 +1,162 @@
+//! Regression tests for the `StreamGenerate` parser, run against captured responses
+//! stored in `tests/fixtures/stream_generate`.
+
+use std::path::PathBuf;
+
+use bard_rs::parser::{parse_frames, parse_payloads, parse_stream_generate, FrameDecoder};
+use bard_rs::{GeminiError, ImageKind};
+
+fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/stream_generate")
+        .join(name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+}
+
+#[test]
+fn single_frame() {
+    let response = parse_stream_generate(&fixture("single_frame.txt")).unwrap();
+
+    assert_eq!(response.content, "Hello there! How can I help you today?");
+    assert_eq!(response.text_query, "hello");
+    assert_eq!(response.drafts.len(), 3);
+    assert_eq!(
+        response.other_drafts()[0].text,
+        "Hi! What can I do for you?"
+    );
+    assert_eq!(
+        response.conversation_ids.conversation_id,
+        "c_4f6d9b2a1e3c5d7f"
+    );
+    assert_eq!(response.conversation_ids.response_id, "r_9a8b7c6d5e4f3a2b");
+    assert_eq!(response.conversation_ids.choice_id, "rc_101b2c3d4e5f6a7b");
+    assert_eq!(response.location, None);
+}
+
+#[test]
+fn picks_last_payload_of_a_streamed_answer() {
+    let body = fixture("multi_frame_streaming.txt");
+
+    assert_eq!(parse_frames(&body).unwrap().len(), 5);
+    assert_eq!(parse_payloads(&body).unwrap().len(), 3);
+
+    let response = parse_stream_generate(&body).unwrap();
+    assert!(response.content.starts_with("Sure, here is a joke"));
+    assert!(response.content.ends_with("```"));
+}
+
+#[test]
+fn answer_after_metadata_and_empty_envelopes() {
+    let response = parse_stream_generate(&fixture("reordered_frames.txt")).unwrap();
+
+    assert_eq!(response.content, "Paris is the capital of France.");
+}
+
+#[test]
+fn choice_id_falls_back_to_rc_prefix() {
+    let response = parse_stream_generate(&fixture("single_draft_without_id.txt")).unwrap();
+
+    assert_eq!(response.drafts[0].id, "");
+    assert_eq!(response.conversation_ids.choice_id, "rc_3c4d5e6f7a8b9c0d");
+}
+
+#[test]
+fn location() {
+    let response = parse_stream_generate(&fixture("location.txt")).unwrap();
+    let location = response.location.unwrap();
+
+    assert_eq!(location.address, "Gangnam-gu, Seoul, South Korea");
+    assert_eq!(
+        location.place_type.as_deref(),
+        Some("Based on your IP address")
+    );
+}
+
+#[test]
+fn images() {
+    let response = parse_stream_generate(&fixture("images.txt")).unwrap();
+
+    assert_eq!(response.images.len(), 2);
+    let web = &response.images[0];
+    assert_eq!(web.kind, ImageKind::Web);
+    assert_eq!(
+        web.url,
+        "https://upload.wikimedia.org/wikipedia/commons/f/fa/Red_fox.jpg"
+    );
+    assert_eq!(web.alt, "Red fox in the snow");
+    assert_eq!(
+        web.source.as_deref(),
+        Some("https://en.wikipedia.org/wiki/Red_fox")
+    );
+    let generated = &response.images[1];
+    assert_eq!(generated.kind, ImageKind::Generated);
+    assert_eq!(
+        generated.url,
+        "https://lh3.googleusercontent.com/gg/AOxT4fox"
+    );
+    assert_eq!(generated.alt, "A watercolor fox");
+    assert_eq!(generated.source, None);
+
+    assert_eq!(response.drafts[0].images, response.images);
+    assert!(response.drafts[1].images.is_empty());
+}
+
+#[test]
+fn lengths_counted_in_utf16_units() {
+    let response = parse_stream_generate(&fixture("unicode_lengths.txt")).unwrap();
+
+    assert_eq!(response.content, "안녕하세요! 무엇을 도와드릴까요? 😊");
+    assert_eq!(response.text_query, "안녕");
+}
+
+#[test]
+fn decoder_reassembles_frames_split_inside_characters() {
+    let body = fixture("unicode_lengths.txt");
+    let bytes = body.as_bytes();
+    let first = body.find('안').unwrap();
+    // one byte into `안`, then chunks that keep landing inside characters
+    let mut cuts = vec![0, first + 1];
+    cuts.extend((first + 1..bytes.len()).step_by(5).skip(1));
+    cuts.push(bytes.len());
+
+    let mut decoder = FrameDecoder::new();
+    let mut frames = Vec::new();
+    for chunk in cuts.windows(2) {
+        frames.extend(decoder.push(&bytes[chunk[0]..chunk[1]]).unwrap());
+    }
+
+    assert!(std::str::from_utf8(&bytes[..first + 1]).is_err());
+    assert_eq!(frames, parse_frames(&body).unwrap());
+}
+
+#[test]
+fn decoder_waits_for_the_rest_of_the_prefix() {
+    let body = fixture("single_frame.txt");
+    let mut decoder = FrameDecoder::new();
+
+    assert!(decoder.push(b")]").unwrap().is_empty());
+    assert!(decoder.push(b"}").unwrap().is_empty());
+    let frames = decoder.push(&body.as_bytes()[3..]).unwrap();
+    assert_eq!(frames, parse_frames(&body).unwrap());
+}
+
+#[test]
+fn ignores_truncated_trailing_frame() {
+    let response = parse_stream_generate(&fixture("truncated_trailing_frame.txt")).unwrap();
+
+    assert_eq!(response.content, "The first part is complete.");
+}
+
+#[test]
+fn error_frame_is_unexpected_payload() {
+    let err = parse_stream_generate(&fixture("error_frame.txt")).unwrap_err();
+
+    assert!(matches!(err, GeminiError::UnexpectedPayload { .. }));
+}
+
+#[test]
+fn garbage_is_unexpected_payload() {
+    let err = parse_stream_generate(")]}'\n\n12\n<html>").unwrap_err();
+
+    assert!(matches!(err, GeminiError::UnexpectedPayload { .. }));
+}
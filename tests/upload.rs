@@ -0,0 +1,64 @@
+//! Attachments uploaded with the resumable protocol and sent with the next prompt.
+
+mod common;
+
+use bard_rs::Attachment;
+use common::{MockGemini, Upload};
+use serde_json::json;
+
+#[tokio::test]
+async fn attachments_go_with_the_next_prompt_only() {
+    let (mock, mut client) = MockGemini::start().await;
+    let dir = std::env::temp_dir().join(format!("bard-rs-upload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fox.png");
+    std::fs::write(&path, b"\x89PNG fox").unwrap();
+
+    let attachment = client.attach_image(&path).await.unwrap().clone();
+    assert_eq!(
+        attachment,
+        Attachment {
+            file_id: "/contrib_service/ttl_1d/file-1".to_string(),
+            name: "fox.png".to_string(),
+        }
+    );
+    assert_eq!(
+        mock.uploads(),
+        [Upload {
+            name: "fox.png".to_string(),
+            bytes: b"\x89PNG fox".to_vec(),
+        }]
+    );
+
+    client.ask("What is this?").await.unwrap();
+    assert!(client.attachments().is_empty());
+    client.ask("And now?").await.unwrap();
+
+    let messages = mock.messages();
+    assert_eq!(
+        messages[0][0],
+        json!([
+            "What is this?",
+            0,
+            null,
+            [[["/contrib_service/ttl_1d/file-1", 1], "fox.png"]]
+        ])
+    );
+    assert_eq!(messages[1][0], json!(["And now?"]));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn cleared_attachments_are_not_sent() {
+    let (mock, mut client) = MockGemini::start().await;
+    let path = std::env::temp_dir().join(format!("bard-rs-upload-{}.txt", std::process::id()));
+    std::fs::write(&path, "notes").unwrap();
+
+    client.attach_image(&path).await.unwrap();
+    client.clear_attachments();
+    client.ask("hi").await.unwrap();
+
+    assert_eq!(mock.messages()[0][0], json!(["hi"]));
+    std::fs::remove_file(&path).unwrap();
+}